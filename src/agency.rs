@@ -0,0 +1,107 @@
+use tfl::line::{Line, Mode};
+
+/// GTFS agency operating one or more lines
+pub struct Agency {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub url: &'static str,
+    pub phone: &'static str,
+}
+
+const TFL: &'static str = "tfl";
+const NATIONAL_RAIL: &'static str = "national-rail";
+const THAMES_CLIPPERS: &'static str = "thames-clippers";
+
+/// River bus line run by TfL itself
+const WOOLWICH_FERRY: &'static str = "woolwich-ferry";
+
+/// Known agencies. Train operating companies and river tour operators are
+/// named exactly as the lines TfL lists for them.
+const AGENCIES: &'static [Agency] = &[
+    Agency { id: TFL, name: "Transport For London", url: "https://tfl.gov.uk", phone: "0343 222 1234" },
+    Agency { id: NATIONAL_RAIL, name: "National Rail", url: "http://www.nationalrail.co.uk", phone: "03457 48 49 50" },
+    Agency { id: THAMES_CLIPPERS, name: "MBNA Thames Clippers", url: "http://www.thamesclippers.com", phone: "020 7001 2200" },
+    Agency { id: "city-cruises", name: "City Cruises", url: "https://www.citycruises.com", phone: "020 7740 0400" },
+    Agency { id: "crown-river-cruises", name: "Crown River Cruises", url: "http://www.crownrivercruise.co.uk", phone: "020 7936 2033" },
+    Agency { id: "thames-river-boats", name: "Thames River Boats", url: "http://www.wpsa.co.uk", phone: "020 7930 2062" },
+    Agency { id: "thames-river-services", name: "Thames River Services", url: "http://www.thamesriverservices.co.uk", phone: "020 7930 4097" },
+    Agency { id: "arriva-trains-wales", name: "Arriva Trains Wales", url: "http://www.arrivatrainswales.co.uk", phone: "" },
+    Agency { id: "c2c", name: "c2c", url: "http://www.c2c-online.co.uk", phone: "03457 444 422" },
+    Agency { id: "chiltern-railways", name: "Chiltern Railways", url: "http://www.chilternrailways.co.uk", phone: "03456 005 165" },
+    Agency { id: "cross-country", name: "Cross Country", url: "https://www.crosscountrytrains.co.uk", phone: "" },
+    Agency { id: "east-midlands-trains", name: "East Midlands Trains", url: "https://www.eastmidlandstrains.co.uk", phone: "" },
+    Agency { id: "first-great-western", name: "First Great Western", url: "https://www.gwr.com", phone: "" },
+    Agency { id: "first-hull-trains", name: "First Hull Trains", url: "https://www.hulltrains.co.uk", phone: "" },
+    Agency { id: "first-transpennine-express", name: "First TransPennine Express", url: "https://www.tpexpress.co.uk", phone: "" },
+    Agency { id: "gatwick-express", name: "Gatwick Express", url: "http://www.gatwickexpress.com", phone: "" },
+    Agency { id: "grand-central", name: "Grand Central", url: "https://www.grandcentralrail.com", phone: "" },
+    Agency { id: "great-northern", name: "Great Northern", url: "http://www.greatnorthernrail.com", phone: "0345 026 4700" },
+    Agency { id: "greater-anglia", name: "Greater Anglia", url: "https://www.greateranglia.co.uk", phone: "0345 600 7245" },
+    Agency { id: "heathrow-connect", name: "Heathrow Connect", url: "https://www.heathrowconnect.com", phone: "" },
+    Agency { id: "heathrow-express", name: "Heathrow Express", url: "https://www.heathrowexpress.com", phone: "0345 600 1515" },
+    Agency { id: "island-line", name: "Island Line", url: "https://www.southwesttrains.co.uk", phone: "" },
+    Agency { id: "london-midland", name: "London Midland", url: "http://www.londonmidland.com", phone: "" },
+    Agency { id: "merseyrail", name: "Merseyrail", url: "https://www.merseyrail.org", phone: "" },
+    Agency { id: "northern-rail", name: "Northern Rail", url: "http://www.northernrail.org", phone: "" },
+    Agency { id: "scotrail", name: "ScotRail", url: "https://www.scotrail.co.uk", phone: "" },
+    Agency { id: "south-west-trains", name: "South West Trains", url: "https://www.southwesttrains.co.uk", phone: "0345 6000 650" },
+    Agency { id: "southeastern", name: "Southeastern", url: "http://www.southeasternrailway.co.uk", phone: "0345 322 7021" },
+    Agency { id: "southern", name: "Southern", url: "http://www.southernrailway.com", phone: "0345 127 2920" },
+    Agency { id: "thameslink", name: "Thameslink", url: "http://www.thameslinkrailway.com", phone: "0345 026 4700" },
+    Agency { id: "virgin-trains", name: "Virgin Trains", url: "https://www.virgintrains.co.uk", phone: "" },
+    Agency { id: "virgin-trains-east-coast", name: "Virgin Trains East Coast", url: "https://www.virgintrainseastcoast.com", phone: "" },
+];
+
+fn agency(id: &str) -> &'static Agency {
+    AGENCIES.iter().find(|agency| agency.id == id).unwrap()
+}
+
+/// Agency named as the given line
+fn named_agency(line: &Line) -> Option<&'static Agency> {
+    AGENCIES.iter().find(|agency| agency.name == line.name)
+}
+
+/// Agency operating the given line. The rb river bus lines are Thames
+/// Clippers', the Woolwich Ferry is TfL's and other river lines are looked up
+/// by name. Unknown train operating companies fall back to National Rail,
+/// unknown river operators and everything else to TfL.
+pub fn line_agency(line: &Line) -> &'static Agency {
+    match line.mode {
+        Mode::NationalRail => {
+            named_agency(line).unwrap_or_else(|| {
+                warn!("Missing national rail operator for {}", line.name);
+                agency(NATIONAL_RAIL)
+            })
+        },
+        Mode::RiverBus if line.id == WOOLWICH_FERRY => agency(TFL),
+        Mode::RiverBus if line.id.starts_with("rb") => agency(THAMES_CLIPPERS),
+        Mode::RiverBus | Mode::RiverTour => {
+            named_agency(line).unwrap_or_else(|| {
+                warn!("Missing river operator for {}, using TfL", line.name);
+                agency(TFL)
+            })
+        },
+        _ => agency(TFL),
+    }
+}
+
+#[test]
+fn line_agency_test() {
+    let line = |id: &str, name: &str, mode: Mode| Line {
+        id: id.to_owned(),
+        name: name.to_owned(),
+        mode: mode,
+        route_sections: vec![],
+        stops: None,
+        inbound_sequence: None,
+        outbound_sequence: None,
+    };
+
+    assert_eq!(line_agency(&line("southern", "Southern", Mode::NationalRail)).id, "southern");
+    assert_eq!(line_agency(&line("unknown", "Unknown Rail", Mode::NationalRail)).id, NATIONAL_RAIL);
+    assert_eq!(line_agency(&line("rb1", "RB1", Mode::RiverBus)).id, THAMES_CLIPPERS);
+    assert_eq!(line_agency(&line(WOOLWICH_FERRY, "Woolwich Ferry", Mode::RiverBus)).id, TFL);
+    assert_eq!(line_agency(&line("city-cruises", "City Cruises", Mode::RiverTour)).id, "city-cruises");
+    assert_eq!(line_agency(&line("unknown", "Unknown Cruise", Mode::RiverTour)).id, TFL);
+    assert_eq!(line_agency(&line("victoria", "Victoria", Mode::Tube)).id, TFL);
+}
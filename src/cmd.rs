@@ -1,33 +1,28 @@
-use ansi_term::Colour::{Green, Red, White, Blue};
 use rand::distributions::{IndependentSample, Range};
 use rand;
 use scoped_threadpool::Pool;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::process;
 use std::sync::Arc;
 
 use format::{OutputFormat};
-use gtfs::{write_gtfs, route_section_id};
-use tfl::line::{Line};
-use tfl::client::{Client, DataSource};
+use geojson::write_geojson;
+use gtfs::{self, route_section_id, FeedWriter};
+use patterns::{self, interval_pattern, section_pattern};
+use progress::Progress;
+use report::{self, LineReport};
+use tfl::line::{Direction, Line, Mode};
+use tfl::client::{Client, ClientOptions, DataSource};
 
+/// Lines fetched and converted at once per thread, bounding memory use
+const LINES_PER_THREAD: usize = 4;
 
-pub fn fetch_lines(format: OutputFormat, thread_number: u32, sample_size: Option<usize>) {
-    let lines = load_lines(DataSource::API, thread_number, sample_size);
-
-    match format {
-        OutputFormat::GTFS => transform_gtfs(lines),
-        _ => process::exit(0),
-    }
+pub fn fetch_lines(format: OutputFormat, thread_number: u32, sample_size: Option<usize>, client_options: ClientOptions, gtfs_options: gtfs::Options, show_progress: bool) {
+    process_lines(DataSource::API, format, thread_number, sample_size, &client_options, &gtfs_options, show_progress);
 }
 
-pub fn transform(format: OutputFormat, thread_number: u32, sample_size: Option<usize>) {
-    let lines = load_lines(DataSource::Cache, thread_number, sample_size);
-
-    match format {
-        OutputFormat::GTFS => transform_gtfs(lines),
-        _ => process::exit(0),
-    }
+pub fn transform(format: OutputFormat, thread_number: u32, sample_size: Option<usize>, client_options: ClientOptions, gtfs_options: gtfs::Options, show_progress: bool) {
+    process_lines(DataSource::Cache, format, thread_number, sample_size, &client_options, &gtfs_options, show_progress);
 }
 
 fn sample<T: Clone>(xs: Vec<T>, size: usize) -> Vec<T> {
@@ -41,7 +36,7 @@ fn sample<T: Clone>(xs: Vec<T>, size: usize) -> Vec<T> {
     let lower = seed;
     let upper = seed + size;
 
-    println!("{}: {}..{}", Green.bold().paint("Sample window"), lower, upper);
+    info!("Sample window: {}..{}", lower, upper);
 
     xs[lower .. upper].to_vec()
 }
@@ -52,86 +47,267 @@ fn sample_test() {
     assert_eq!(sample(vec![0; 100], 10).len(), 10);
 }
 
-fn load_lines(data_source: DataSource, thread_number: u32, sample_size: Option<usize>) -> Vec<Line> {
-    let mut pool = Pool::new(thread_number);
-    let client = Arc::new(Client::new());
-
-    let mut lines = match data_source {
+fn line_index(client: &Client, data_source: DataSource, sample_size: Option<usize>, modes: &[Mode]) -> Vec<Line> {
+    let lines = match data_source {
         DataSource::Cache => client.get_cached_lines(),
         DataSource::API   => client.get_lines(),
     };
 
+    let (mut lines, duplicates) = merge_duplicates(lines);
+    info!("Duplicate Lines: {}, Duplicate Route Sections: {}", duplicates.lines, duplicates.route_sections);
+
+    if !modes.is_empty() {
+        lines.retain(|line| modes.contains(&line.mode));
+    }
+
     if let Some(n) = sample_size {
         lines = sample(lines, n);
     }
 
+    lines
+}
+
+/// Number of lines and route sections merged away from the line index
+#[derive(Debug, Default, PartialEq)]
+struct Duplicates {
+    lines: usize,
+    route_sections: usize,
+}
+
+/// Merge the route sections of lines listed more than once into their first
+/// occurrence, then collapse the route sections of a line with the same
+/// originator, destination and direction, so each is converted only once
+fn merge_duplicates(lines: Vec<Line>) -> (Vec<Line>, Duplicates) {
+    let mut duplicates = Duplicates::default();
+    let mut merged: Vec<Line> = Vec::with_capacity(lines.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for line in lines {
+        let position = positions.get(&line.id).cloned();
+        match position {
+            Some(i) => {
+                duplicates.lines += 1;
+                merged[i].route_sections.extend(line.route_sections);
+            },
+            None => {
+                positions.insert(line.id.clone(), merged.len());
+                merged.push(line);
+            },
+        }
+    }
+
+    for line in &mut merged {
+        let mut keys = HashSet::new();
+        let section_count = line.route_sections.len();
+        line.route_sections.retain(|x| keys.insert((x.originator.clone(), x.destination.clone(), x.direction.clone())));
+        duplicates.route_sections += section_count - line.route_sections.len();
+    }
+
+    (merged, duplicates)
+}
+
+#[test]
+fn merge_duplicates_test() {
+    use tfl::line::RouteSection;
+
+    let section = |originator: &str, destination: &str, direction: Direction| RouteSection {
+        name: format!("{} - {}", originator, destination),
+        direction: direction,
+        originator: originator.to_owned(),
+        origination_name: None,
+        destination: destination.to_owned(),
+        destination_name: None,
+        timetable: None,
+    };
+    let line = |id: &str, route_sections: Vec<RouteSection>| Line {
+        id: id.to_owned(),
+        name: id.to_owned(),
+        mode: Mode::Bus,
+        route_sections: route_sections,
+        stops: None,
+        inbound_sequence: None,
+        outbound_sequence: None,
+    };
+    let lines = vec![
+        line("1", vec![section("a", "b", Direction::Outbound), section("a", "b", Direction::Outbound)]),
+        line("2", vec![section("a", "c", Direction::Outbound)]),
+        line("1", vec![section("b", "a", Direction::Inbound), section("a", "b", Direction::Outbound)]),
+    ];
+    let (merged, duplicates) = merge_duplicates(lines);
+
+    assert_eq!(duplicates, Duplicates { lines: 1, route_sections: 2 });
+    assert_eq!(merged.iter().map(|x| &x.id[..]).collect::<Vec<&str>>(), vec!["1", "2"]);
+    assert_eq!(merged[0].route_sections.iter().map(|x| &x.originator[..]).collect::<Vec<&str>>(), vec!["a", "b"]);
+}
+
+fn load_line_details(pool: &mut Pool, client: &Arc<Client>, lines: &mut [Line]) {
     pool.scoped(|scope| {
-        for line in &mut lines {
+        for line in lines {
             let client = client.clone();
             scope.execute(move || {
                 line.inbound_sequence = client.get_sequence(&line.id, "inbound");
                 line.outbound_sequence = client.get_sequence(&line.id, "outbound");
                 line.stops = Some(client.get_stops(&line.id));
                 for route_section in &mut line.route_sections {
-                    println!("{} Timetable", Green.bold().paint("Getting"));
-                    println!("\tLine: {}", Blue.bold().paint(line.name.clone()));
-                    println!("\tRoute Section: {} ...", White.bold().paint(route_section.name.clone()));
+                    debug!("Getting timetable of line {} route section {}", line.name, route_section.name);
                     route_section.timetable = client.get_timetable(&line.id, &route_section.originator, &route_section.destination);
                 }
             });
         }
     });
+}
 
-    lines
+/// Fetch the details of the lines and convert them to the given format in
+/// batches, so only a few lines are held in memory at any time
+fn process_lines(data_source: DataSource, format: OutputFormat, thread_number: u32, sample_size: Option<usize>, client_options: &ClientOptions, gtfs_options: &gtfs::Options, show_progress: bool) {
+    let mut pool = Pool::new(thread_number);
+    let client = Arc::new(Client::new(client_options));
+    let label = match data_source {
+        DataSource::API => "Fetching",
+        DataSource::Cache => "Transforming",
+    };
+    let lines = line_index(&client, data_source, sample_size, &gtfs_options.modes);
+
+    match format {
+        OutputFormat::GTFS | OutputFormat::GeoJSON if lines.is_empty() => {
+            error!("No lines found in the cache, try fetching some data first");
+            process::exit(0);
+        },
+        _ => {},
+    }
+
+    let mut summary = Summary::default();
+    let mut progress = Progress::new(label, lines.len(), show_progress);
+    let mut feed = match format {
+        OutputFormat::GTFS => Some(FeedWriter::new(gtfs_options)),
+        _ => None,
+    };
+
+    for batch in lines.chunks(thread_number as usize * LINES_PER_THREAD) {
+        let mut batch = batch.to_vec();
+        load_line_details(&mut pool, &client, &mut batch);
+
+        match format {
+            OutputFormat::GTFS => {
+                for line in &batch {
+                    summary.add_line(line);
+                }
+                if let Some(ref mut feed) = feed {
+                    feed.write_lines(&mut pool, &batch);
+                }
+            },
+            OutputFormat::GeoJSON => write_geojson(&batch, gtfs_options),
+            _ => {},
+        }
+
+        progress.inc(batch.len());
+    }
+    progress.finish();
+
+    if let Some(feed) = feed {
+        summary.print();
+        let reports = feed.finish();
+        print_diagnostics(&reports);
+        info!("Quality report written to {}", report::write_report(&gtfs_options.reports_dir, &reports));
+    }
 }
 
-fn transform_gtfs(lines: Vec<Line>) {
-    let mut line_count = 0;
-    let mut line_ids: HashSet<String> = HashSet::new();
-    let mut route_section_count = 0;
-    let mut route_section_ids: HashSet<String> = HashSet::new();
-    let mut schedule_names: HashSet<String> = HashSet::new();
+/// Counts and names reported once every line has been converted
+#[derive(Default)]
+struct Summary {
+    schedule_names: HashSet<String>,
+    missing_timetables: usize,
+    timetable_errors: HashSet<String>,
+    derived_patterns: usize,
+    interval_count: usize,
+    matched_intervals: usize,
+    unknown_modes: HashSet<String>,
+    unknown_directions: HashSet<String>,
+}
 
-    for line in &lines {
-        let is_duplicated = if line_ids.contains(&line.id) {
-            Red.paint("yes")
-        } else {
-            Green.paint("no")
-        };
+impl Summary {
+    fn add_line(&mut self, line: &Line) {
+        debug!("Line {}", line.id);
 
-        println!("{}; Duplicate: {}", line, is_duplicated);
+        if let Mode::Unknown(ref mode) = line.mode {
+            self.unknown_modes.insert(format!("{} ({})", mode, line.id));
+        }
 
         for route_section in &line.route_sections {
+            if let Direction::Unknown(ref direction) = route_section.direction {
+                self.unknown_directions.insert(format!("{} ({})", direction, line.id));
+            }
+
             let has_timetable = match route_section.timetable {
                 Some(ref timetable) => {
                     let names = timetable.schedule_names();
-                    schedule_names = schedule_names.union(&names).cloned().collect::<HashSet<String>>();
+                    self.schedule_names = self.schedule_names.union(&names).cloned().collect::<HashSet<String>>();
                     names.is_empty()
                 },
                 None => false,
             };
 
             let id = route_section_id(&line, &route_section);
-            println!("     {}, Has Timetable: {}, Duplicate: {}", id, has_timetable, route_section_ids.contains(&id));
-            route_section_ids.insert(id.clone());
-            route_section_count += 1;
+            debug!("Route section {}, Has Timetable: {}", id, has_timetable);
+
+            if let Some(message) = route_section.timetable.as_ref().and_then(|x| x.status_error_message.as_ref()) {
+                self.timetable_errors.insert(format!("{}: {}", id, message));
+            }
+
+            if !patterns::has_timetable(route_section) {
+                self.missing_timetables += 1;
+                if section_pattern(line, route_section).is_some() {
+                    self.derived_patterns += 1;
+                }
+            } else if let Some(datum) = route_section.timetable.as_ref().and_then(|x| x.first_timetable()) {
+                for interval in &datum.station_intervals {
+                    self.interval_count += 1;
+                    if interval_pattern(line, route_section, interval).is_some() {
+                        self.matched_intervals += 1;
+                    }
+                }
+            }
         }
-        line_count += 1;
-        line_ids.insert(line.id.clone());
     }
 
-    if lines.is_empty() {
-        println!("No lines found in the cache, try fetching some data first");
-        process::exit(0);
-    }
+    fn print(&self) {
+        info!("Route Sections Without Timetable: {}, With Stop Pattern: {}", self.missing_timetables, self.derived_patterns);
+        info!("Station Intervals Matching A Route Sequence: {}/{}", self.matched_intervals, self.interval_count);
+
+        let mut timetable_errors: Vec<&String> = self.timetable_errors.iter().collect();
+        timetable_errors.sort();
+        for error in timetable_errors {
+            warn!("Timetable error on {}", error);
+        }
 
-    println!("Duplicate Lines: {}, Duplicate Route Sections: {}", line_count - line_ids.len(), route_section_count-route_section_ids.len());
+        if !self.unknown_modes.is_empty() {
+            warn!("Unknown Modes: {}", self.unknown_modes.iter().cloned().collect::<Vec<String>>().join(", "));
+        }
+        if !self.unknown_directions.is_empty() {
+            warn!("Unknown Directions: {}", self.unknown_directions.iter().cloned().collect::<Vec<String>>().join(", "));
+        }
 
-    println!("Schedule Names:");
-    for schedule_name in &schedule_names {
-        println!("\t{}", schedule_name);
+        let mut schedule_names: Vec<&String> = self.schedule_names.iter().collect();
+        schedule_names.sort();
+        info!("Schedule Names: {}", schedule_names.iter().map(|x| &x[..]).collect::<Vec<&str>>().join(", "));
     }
+}
 
-    // Generate CSV files from fetched data
-    write_gtfs(&lines);
+fn print_diagnostics(reports: &[LineReport]) {
+    for line_report in reports {
+        let line_id = &line_report.line_id;
+        let line_diagnostics = &line_report.line_strings;
+        if line_diagnostics.has_problems() || line_diagnostics.gaps_closed > 0 {
+            warn!("Line string problems on {}: {} paths from {} line strings, {} skipped, {} undecodable, {} gaps closed",
+                  line_id,
+                  line_diagnostics.paths,
+                  line_diagnostics.line_strings,
+                  line_diagnostics.skipped,
+                  line_diagnostics.errors.len(),
+                  line_diagnostics.gaps_closed);
+            for error in &line_diagnostics.errors {
+                warn!("Line string problem on {}: {}", line_id, error);
+            }
+        }
+    }
 }
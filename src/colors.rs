@@ -0,0 +1,167 @@
+use serde_json;
+use std::collections::HashMap;
+use std::path::Path;
+
+use json_file::read_json_file;
+use tfl::line::Line;
+
+/// Default colour table, embedded at compile time
+const DEFAULT_COLORS: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/colors.json"));
+
+/// Colour table entry. Entries without a line apply to every line of the mode.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ColorEntry {
+    mode: String,
+    #[serde(default)]
+    line: Option<String>,
+    color: String,
+    #[serde(default)]
+    text_color: Option<String>,
+}
+
+/// Route and route text colours as hexadecimal strings
+#[derive(Clone, Debug)]
+pub struct RouteColor {
+    pub color: String,
+    pub text_color: String,
+}
+
+/// Route colours keyed by mode and line id
+#[derive(Clone, Debug)]
+pub struct ColorTable {
+    colors: HashMap<(String, Option<String>), RouteColor>,
+}
+
+impl Default for ColorTable {
+    fn default() -> ColorTable {
+        let mut table = ColorTable { colors: HashMap::new() };
+        table.insert_json(DEFAULT_COLORS).unwrap();
+        table
+    }
+}
+
+impl ColorTable {
+    /// Load a user colour file on top of the current table, its entries
+    /// take precedence over existing ones.
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let entries: Vec<ColorEntry> = try!(read_json_file(path, "colour file"));
+        self.insert(entries).map_err(|err| format!("Invalid colour file {}: {}", path.display(), err))
+    }
+
+    fn insert_json(&mut self, body: &str) -> Result<(), String> {
+        let entries: Vec<ColorEntry> = try!(serde_json::from_str(body).map_err(|err| err.to_string()));
+        self.insert(entries)
+    }
+
+    /// Add entries to the table, taking precedence over existing ones. Fails
+    /// without adding any entry when one of them has an invalid colour.
+    pub fn insert(&mut self, entries: Vec<ColorEntry>) -> Result<(), String> {
+        for entry in &entries {
+            try!(entry.validate());
+        }
+
+        for entry in entries {
+            let color = expand_hex(&entry.color).unwrap();
+            let text_color = match entry.text_color {
+                Some(ref text_color) => expand_hex(text_color).unwrap(),
+                None => contrast_text_color(&color).to_owned(),
+            };
+            self.colors.insert((entry.mode, entry.line), RouteColor {
+                color: color,
+                text_color: text_color,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Colour of a line, falling back to the default colour of its mode
+    pub fn line_color(&self, line: &Line) -> Option<&RouteColor> {
+        let mode = line.mode.as_str().to_owned();
+        self.colors.get(&(mode.clone(), Some(line.id.clone())))
+                   .or_else(|| self.colors.get(&(mode, None)))
+    }
+}
+
+impl ColorEntry {
+    /// Check the colours are 3 or 6 digit hexadecimal strings
+    pub fn validate(&self) -> Result<(), String> {
+        for color in Some(&self.color).into_iter().chain(self.text_color.as_ref()) {
+            if expand_hex(color).is_none() {
+                return Err(format!("invalid colour \"{}\" for mode {}, expected 3 or 6 hexadecimal digits", color, self.mode));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn channel_luminance(channel: u8) -> f64 {
+    let c = channel as f64 / 255.0;
+    if c <= 0.03928 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Six digit form of a 3 or 6 digit hexadecimal colour, as GTFS requires
+fn expand_hex(color: &str) -> Option<String> {
+    if !color.chars().all(|c| c.is_digit(16)) {
+        return None;
+    }
+
+    match color.len() {
+        3 => Some(color.chars().flat_map(|c| vec![c, c]).collect()),
+        6 => Some(color.to_owned()),
+        _ => None,
+    }
+}
+
+/// WCAG relative luminance of a 3 or 6 digit hexadecimal colour
+fn relative_luminance(color: &str) -> Option<f64> {
+    let hex = match expand_hex(color) {
+        Some(hex) => hex,
+        None => return None,
+    };
+    let mut channels = Vec::new();
+
+    for i in 0..3 {
+        match u8::from_str_radix(&hex[i * 2 .. i * 2 + 2], 16) {
+            Ok(channel) => channels.push(channel_luminance(channel)),
+            Err(_) => return None,
+        }
+    }
+
+    Some(0.2126 * channels[0] + 0.7152 * channels[1] + 0.0722 * channels[2])
+}
+
+/// Black or white, whichever contrasts best with the given colour
+pub fn contrast_text_color(color: &str) -> &'static str {
+    match relative_luminance(color) {
+        Some(luminance) => {
+            let black_contrast = (luminance + 0.05) / 0.05;
+            let white_contrast = 1.05 / (luminance + 0.05);
+            if black_contrast > white_contrast { "000000" } else { "FFFFFF" }
+        },
+        None => "",
+    }
+}
+
+#[test]
+fn contrast_text_color_test() {
+    assert_eq!(contrast_text_color("FFCE00"), "000000");
+    assert_eq!(contrast_text_color("000"), "FFFFFF");
+    assert_eq!(contrast_text_color("0019A8"), "FFFFFF");
+    assert_eq!(contrast_text_color("not a colour"), "");
+    assert_eq!(contrast_text_color("0\u{e9}000"), "");
+}
+
+#[test]
+fn insert_test() {
+    let mut table = ColorTable::default();
+
+    assert!(table.insert_json(r#"[{"mode": "bus", "color": "f00"}]"#).is_ok());
+    assert_eq!(table.colors[&("bus".to_owned(), None)].color, "ff0000");
+    assert!(table.insert_json(r#"[{"mode": "bus", "color": "red"}]"#).is_err());
+    assert!(table.insert_json(r#"[{"mode": "bus", "color": "000000", "text_color": "0é000"}]"#).is_err());
+}
@@ -3,6 +3,7 @@ use std::str::FromStr;
 #[derive(Debug)]
 pub enum OutputFormat {
     GTFS,
+    GeoJSON,
     JSON,
     None,
 }
@@ -13,6 +14,7 @@ impl FromStr for OutputFormat {
     fn from_str(value: &str) -> Result<Self, Self::Err> {
         match value {
             "gtfs" => Ok(OutputFormat::GTFS),
+            "geojson" => Ok(OutputFormat::GeoJSON),
             "json" => Ok(OutputFormat::JSON),
             "none" => Ok(OutputFormat::None),
             _     => Err("Unexpected output format")
@@ -0,0 +1,160 @@
+use tfl::line::{KnownJourney, Schedule};
+
+/// Minimum number of journeys in a run before it is worth a frequencies.txt row
+const MIN_RUN_LENGTH: usize = 3;
+
+/// Frequency compaction settings
+#[derive(Clone, Debug)]
+pub struct FrequencyOptions {
+    /// Maximum deviation in seconds between consecutive headways of a run
+    pub tolerance: u64,
+    /// Emit frequencies of exactly constant runs as exact_times=1 (schedule
+    /// based) instead of 0
+    pub exact_times: bool,
+}
+
+impl Default for FrequencyOptions {
+    fn default() -> FrequencyOptions {
+        FrequencyOptions {
+            tolerance: 60,
+            exact_times: false,
+        }
+    }
+}
+
+/// Run of journeys sharing a station interval and departing at a constant
+/// headway. Runs too short to be compacted hold a single journey.
+pub struct JourneyRun<'a> {
+    pub journeys: Vec<&'a KnownJourney>,
+    /// Headway in seconds, zero for single journey runs
+    pub headway: u64,
+}
+
+impl<'a> JourneyRun<'a> {
+    /// Journey used as the template trip for the whole run
+    pub fn template(&self) -> &'a KnownJourney {
+        self.journeys[0]
+    }
+
+    /// Whether the run should be written as a frequencies.txt row
+    pub fn is_frequency(&self) -> bool {
+        self.journeys.len() >= MIN_RUN_LENGTH
+    }
+
+    /// Departure of the first journey, in seconds since midnight
+    pub fn start_time(&self) -> u64 {
+        departure_seconds(self.template())
+    }
+
+    /// Whether every journey departs exactly one headway after the previous
+    /// one, so the run can be expanded into its exact departures
+    pub fn is_exact(&self) -> bool {
+        self.journeys.windows(2).all(|pair| departure_seconds(pair[1]) - departure_seconds(pair[0]) == self.headway)
+    }
+
+    /// Time at which the headway ceases, in seconds since midnight
+    pub fn end_time(&self) -> u64 {
+        departure_seconds(self.journeys[self.journeys.len() - 1]) + self.headway
+    }
+}
+
+/// Departure time of a known journey in seconds since midnight
+pub fn departure_seconds(journey: &KnownJourney) -> u64 {
+    let hour : u64 = journey.hour.parse().unwrap();
+    let minute : u64 = journey.minute.parse().unwrap();
+    hour * 3600 + minute * 60
+}
+
+/// Format seconds since midnight as a GTFS time, hours may exceed 24
+pub fn seconds_fmt(seconds: u64) -> String {
+    format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds % 3600) / 60, seconds % 60)
+}
+
+fn headway_matches(headway: u64, expected: u64, tolerance: u64) -> bool {
+    headway > 0 && (headway as i64 - expected as i64).abs() as u64 <= tolerance
+}
+
+/// Split the known journeys of a schedule into constant headway runs.
+/// Journeys are grouped by station interval, since only journeys sharing
+/// stop times can share a template trip, and sorted by departure time.
+pub fn journey_runs<'a>(schedule: &'a Schedule, options: &FrequencyOptions) -> Vec<JourneyRun<'a>> {
+    let mut groups: Vec<(i64, Vec<&KnownJourney>)> = Vec::new();
+
+    for journey in &schedule.known_journeys {
+        let position = groups.iter().position(|&(id, _)| id == journey.interval_id);
+        match position {
+            Some(i) => groups[i].1.push(journey),
+            None => groups.push((journey.interval_id, vec![journey])),
+        }
+    }
+
+    let mut runs = Vec::new();
+
+    for (_, mut journeys) in groups {
+        journeys.sort_by_key(|journey| departure_seconds(journey));
+        journeys.dedup_by_key(|journey| departure_seconds(journey));
+
+        let times: Vec<u64> = journeys.iter().map(|journey| departure_seconds(journey)).collect();
+        let mut i = 0;
+
+        while i < journeys.len() {
+            let mut j = i;
+
+            if i + 1 < journeys.len() {
+                let expected = times[i + 1] - times[i];
+                j = i + 1;
+                while j + 1 < journeys.len() && headway_matches(times[j + 1] - times[j], expected, options.tolerance) {
+                    j += 1;
+                }
+            }
+
+            if j + 1 - i >= MIN_RUN_LENGTH {
+                let count = (j - i) as u64;
+                let headway = (times[j] - times[i] + count / 2) / count;
+                runs.push(JourneyRun { journeys: journeys[i .. j + 1].to_vec(), headway: headway });
+                i = j + 1;
+            } else {
+                runs.push(JourneyRun { journeys: vec![journeys[i]], headway: 0 });
+                i += 1;
+            }
+        }
+    }
+
+    runs
+}
+
+#[test]
+fn journey_runs_test() {
+    let journey = |interval_id, hour: &str, minute: &str| KnownJourney {
+        interval_id: interval_id,
+        hour: hour.to_owned(),
+        minute: minute.to_owned(),
+    };
+    let schedule = Schedule {
+        name: "Monday - Friday".to_owned(),
+        known_journeys: vec![
+            journey(0, "7", "00"),
+            journey(0, "7", "05"),
+            journey(0, "7", "10"),
+            journey(0, "7", "16"),
+            journey(0, "7", "40"),
+            journey(1, "7", "02"),
+            journey(2, "8", "00"),
+            journey(2, "8", "10"),
+            journey(2, "8", "20"),
+        ],
+    };
+    let options = FrequencyOptions { tolerance: 60, exact_times: false };
+    let runs = journey_runs(&schedule, &options);
+
+    assert_eq!(runs.len(), 4);
+    assert_eq!(runs[0].journeys.len(), 4);
+    assert_eq!(runs[0].headway, 320);
+    assert_eq!(seconds_fmt(runs[0].end_time()), "07:21:20");
+    assert!(!runs[0].is_exact());
+    assert!(!runs[1].is_frequency());
+    assert!(!runs[2].is_frequency());
+    assert!(runs[3].is_frequency());
+    assert_eq!(runs[3].headway, 600);
+    assert!(runs[3].is_exact());
+}
@@ -0,0 +1,235 @@
+use std::f64::consts::PI;
+
+use geometry::Point;
+
+/// Mean earth radius in metres
+pub const EARTH_RADIUS: f64 = 6371000.0;
+
+/// Degrees to radians
+fn deg2rad(deg: f64) -> f64 {
+    (PI / 180.0) * deg
+}
+
+/// Radians to degrees
+fn rad2deg(rad: f64) -> f64 {
+    (180.0 / PI) * rad
+}
+
+/// Great circle distance in metres between two points
+pub fn haversine(a: &Point, b: &Point) -> f64 {
+    let phi1 = deg2rad(a.lat());
+    let phi2 = deg2rad(b.lat());
+    let delta_phi = deg2rad(b.lat() - a.lat());
+    let delta_lambda = deg2rad(b.lon() - a.lon());
+    let h = (delta_phi / 2.0).sin() * (delta_phi / 2.0).sin() +
+        phi1.cos() * phi2.cos() *
+        (delta_lambda / 2.0).sin() * (delta_lambda / 2.0).sin();
+    2.0 * EARTH_RADIUS * h.sqrt().atan2((1.0 - h).sqrt())
+}
+
+/// Initial bearing in degrees clockwise from north, in [0, 360), when
+/// travelling from a to b along a great circle
+pub fn initial_bearing(a: &Point, b: &Point) -> f64 {
+    let phi1 = deg2rad(a.lat());
+    let phi2 = deg2rad(b.lat());
+    let delta_lambda = deg2rad(b.lon() - a.lon());
+    let y = delta_lambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * delta_lambda.cos();
+    (rad2deg(y.atan2(x)) + 360.0) % 360.0
+}
+
+/// Point reached travelling distance metres from start along a great circle
+/// with the given initial bearing in degrees
+pub fn destination(start: &Point, bearing: f64, distance: f64) -> Point {
+    let phi1 = deg2rad(start.lat());
+    let lambda1 = deg2rad(start.lon());
+    let theta = deg2rad(bearing);
+    let delta = distance / EARTH_RADIUS;
+    let phi2 = (phi1.sin() * delta.cos() + phi1.cos() * delta.sin() * theta.cos()).asin();
+    let lambda2 = lambda1 + (theta.sin() * delta.sin() * phi1.cos()).atan2(delta.cos() - phi1.sin() * phi2.sin());
+    Point::new(rad2deg(phi2), (rad2deg(lambda2) + 540.0) % 360.0 - 180.0)
+}
+
+/// Fraction along the segment a-b of the point closest to p, clamped to
+/// [0, 1]. Uses an equirectangular approximation which is plenty accurate
+/// at the scale of a single segment.
+fn segment_fraction(a: &Point, b: &Point, p: &Point) -> f64 {
+    let scale = deg2rad(a.lat()).cos();
+    let (ax, ay) = (a.lon() * scale, a.lat());
+    let (bx, by) = (b.lon() * scale, b.lat());
+    let (px, py) = (p.lon() * scale, p.lat());
+    let (dx, dy) = (bx - ax, by - ay);
+    let len2 = dx * dx + dy * dy;
+
+    if len2 == 0.0 {
+        return 0.0;
+    }
+
+    let t = ((px - ax) * dx + (py - ay) * dy) / len2;
+    t.max(0.0).min(1.0)
+}
+
+/// Point at the given fraction of the segment a-b
+fn interpolate(a: &Point, b: &Point, fraction: f64) -> Point {
+    Point::new(a.lat() + (b.lat() - a.lat()) * fraction, a.lon() + (b.lon() - a.lon()) * fraction)
+}
+
+/// Distance in metres from p to the closest point of the segment a-b
+pub fn segment_distance(p: &Point, a: &Point, b: &Point) -> f64 {
+    haversine(p, &interpolate(a, b, segment_fraction(a, b, p)))
+}
+
+/// Length of a path in metres
+pub fn path_length(path: &[Point]) -> f64 {
+    path.windows(2).map(|pair| haversine(&pair[0], &pair[1])).sum()
+}
+
+/// Cumulative distance in metres from the start of the path to each point
+pub fn path_distances(path: &[Point]) -> Vec<f64> {
+    let mut total = 0.0;
+    let mut distances = Vec::with_capacity(path.len());
+    for (i, pt) in path.iter().enumerate() {
+        if i > 0 {
+            total += haversine(&path[i - 1], pt);
+        }
+        distances.push(total);
+    }
+    distances
+}
+
+/// Projection of a point onto a polyline
+#[derive(Clone, Debug)]
+pub struct Projection {
+    /// Index of the segment the point projects onto
+    pub segment: usize,
+    /// Fraction along that segment
+    pub fraction: f64,
+    /// Projected point on the polyline
+    pub point: Point,
+    /// Distance in metres along the polyline to the projected point
+    pub distance_along: f64,
+    /// Distance in metres between the point and its projection
+    pub offset: f64,
+}
+
+/// Project a point onto a polyline, considering only segments from `start`
+/// onwards so consecutive points progress along it. Distances are the
+/// cumulative distances of `path_distances`. Returns None for an empty path
+/// and, for paths of two or more points, when `start` leaves no segment.
+pub fn project_onto_polyline(path: &[Point], distances: &[f64], pt: &Point, start: usize) -> Option<Projection> {
+    let mut best: Option<Projection> = None;
+
+    if path.len() == 1 {
+        return Some(Projection {
+            segment: 0,
+            fraction: 0.0,
+            point: path[0],
+            distance_along: 0.0,
+            offset: haversine(&path[0], pt),
+        });
+    }
+
+    for i in start .. path.len().saturating_sub(1) {
+        let (a, b) = (&path[i], &path[i + 1]);
+        let fraction = segment_fraction(a, b, pt);
+        let projected = interpolate(a, b, fraction);
+        let offset = haversine(&projected, pt);
+
+        if best.as_ref().map_or(true, |x| offset < x.offset) {
+            best = Some(Projection {
+                segment: i,
+                fraction: fraction,
+                point: projected,
+                distance_along: distances[i] + (distances[i + 1] - distances[i]) * fraction,
+                offset: offset,
+            });
+        }
+    }
+
+    best
+}
+
+#[test]
+fn haversine_test() {
+    let kings_cross = Point::new(51.5308, -0.1238);
+    let euston = Point::new(51.5282, -0.1337);
+    let london = Point::new(51.5074, -0.1278);
+    let paris = Point::new(48.8566, 2.3522);
+
+    assert!((haversine(&Point::new(51.0, 0.0), &Point::new(52.0, 0.0)) - 111194.9).abs() < 0.1);
+    assert!((haversine(&kings_cross, &euston) - 743.4).abs() < 0.1);
+    assert!((haversine(&london, &paris) - 343556.1).abs() < 0.1);
+    assert_eq!(haversine(&euston, &euston), 0.0);
+}
+
+#[test]
+fn bearing_and_destination_test() {
+    let london = Point::new(51.5074, -0.1278);
+    let paris = Point::new(48.8566, 2.3522);
+    let kings_cross = Point::new(51.5308, -0.1238);
+    let euston = Point::new(51.5282, -0.1337);
+
+    assert!((initial_bearing(&london, &paris) - 148.1).abs() < 0.1);
+    assert!((initial_bearing(&kings_cross, &euston) - 247.1).abs() < 0.1);
+
+    let reached = destination(&kings_cross, initial_bearing(&kings_cross, &euston), haversine(&kings_cross, &euston));
+    assert!(haversine(&reached, &euston) < 0.01);
+}
+
+#[test]
+fn projection_test() {
+    let a = Point::new(51.5000, -0.1200);
+    let b = Point::new(51.5000, -0.1100);
+    let c = Point::new(51.5100, -0.1100);
+    let path = vec![a, b, c];
+    let distances = path_distances(&path);
+    let pt = Point::new(51.5010, -0.1150);
+
+    assert!((segment_distance(&pt, &a, &b) - 111.2).abs() < 0.1);
+    assert!((path_length(&path) - distances[2]).abs() < 1e-9);
+
+    let projection = project_onto_polyline(&path, &distances, &pt, 0).unwrap();
+    assert_eq!(projection.segment, 0);
+    assert!((projection.fraction - 0.5).abs() < 1e-6);
+    assert!((projection.distance_along - distances[1] / 2.0).abs() < 0.01);
+
+    let later = project_onto_polyline(&path, &distances, &pt, 1).unwrap();
+    assert_eq!(later.segment, 1);
+    assert!((later.fraction - 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn path_distances_test() {
+    let a = Point::new(51.5000, -0.1200);
+    let b = Point::new(51.5000, -0.1100);
+    let c = Point::new(51.5100, -0.1100);
+    let distances = path_distances(&[a, b, c]);
+
+    assert_eq!(distances[0], 0.0);
+    assert_eq!(distances[1], haversine(&a, &b));
+    assert_eq!(distances[2], haversine(&a, &b) + haversine(&b, &c));
+    assert!(path_distances(&[]).is_empty());
+    assert_eq!(path_distances(&[a]), vec![0.0]);
+}
+
+#[test]
+fn projection_edge_cases_test() {
+    let a = Point::new(51.5000, -0.1200);
+    let b = Point::new(51.5000, -0.1100);
+    let pt = Point::new(51.5010, -0.1300);
+
+    assert!(project_onto_polyline(&[], &[], &pt, 0).is_none());
+
+    let single = project_onto_polyline(&[a], &[0.0], &pt, 0).unwrap();
+    assert_eq!(single.distance_along, 0.0);
+    assert_eq!(single.offset, haversine(&a, &pt));
+
+    // points before the start of the path project onto its first point
+    let path = vec![a, b];
+    let before = project_onto_polyline(&path, &path_distances(&path), &pt, 0).unwrap();
+    assert_eq!(before.fraction, 0.0);
+    assert_eq!(before.distance_along, 0.0);
+
+    // no segment starts at or after the last point
+    assert!(project_onto_polyline(&path, &path_distances(&path), &pt, 1).is_none());
+}
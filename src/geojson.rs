@@ -0,0 +1,161 @@
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::Path;
+
+use gtfs::{line_shapes, line_stops, route_section_id, Options, Route};
+use tfl::line::Line;
+
+#[derive(Serialize)]
+struct Geometry<C> {
+    #[serde(rename="type")]
+    kind: &'static str,
+    coordinates: C,
+}
+
+#[derive(Serialize)]
+struct Feature<C> {
+    #[serde(rename="type")]
+    kind: &'static str,
+    geometry: Geometry<C>,
+    properties: BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+struct FeatureCollection {
+    #[serde(rename="type")]
+    kind: &'static str,
+    features: Vec<Value>,
+}
+
+fn feature<C>(kind: &'static str, coordinates: C, properties: BTreeMap<&'static str, String>) -> Feature<C> {
+    Feature {
+        kind: "Feature",
+        geometry: Geometry {
+            kind: kind,
+            coordinates: coordinates,
+        },
+        properties: properties,
+    }
+}
+
+/// Properties shared by every feature of a line
+fn line_properties(line: &Line, options: &Options) -> BTreeMap<&'static str, String> {
+    let color = options.colors.line_color(line).map_or(String::new(), |x| format!("#{}", x.color));
+    let mut properties = BTreeMap::new();
+    properties.insert("line_id", line.id.clone());
+    properties.insert("line_name", line.name.clone());
+    properties.insert("mode", line.mode.as_str().to_owned());
+    properties.insert("color", color);
+    properties
+}
+
+/// FeatureCollection of the route section shapes and stops of a line
+fn line_features(line: &Line, options: &Options) -> FeatureCollection {
+    let route = Route::new(line, options);
+    let stops = line_stops(line);
+    let stop_locations: HashMap<String, (f64, f64)> = stops.iter().map(|&(ref id, _, lat, lon)| (id.clone(), (lat, lon))).collect();
+    let shapes = line_shapes(&route, &stop_locations, &options.shapes);
+    let mut features = Vec::new();
+
+    for section in &line.route_sections {
+        let shape_id = route_section_id(line, section);
+
+        if let Some(path) = shapes.get(&shape_id) {
+            let coordinates: Vec<(f64, f64)> = path.iter().map(|pt| (pt.lon(), pt.lat())).collect();
+            let mut properties = line_properties(line, options);
+            let stroke = properties["color"].clone();
+            properties.insert("shape_id", shape_id);
+            properties.insert("route_section", section.name.clone());
+            properties.insert("direction", section.direction.as_str().to_owned());
+            properties.insert("originator", section.originator.clone());
+            properties.insert("destination", section.destination.clone());
+            properties.insert("stroke", stroke);
+            features.push(serde_json::to_value(&feature("LineString", coordinates, properties)));
+        }
+    }
+
+    let mut written = HashSet::new();
+    for (id, name, lat, lon) in stops {
+        if written.insert(id.clone()) {
+            let mut properties = line_properties(line, options);
+            let marker_color = properties["color"].clone();
+            properties.insert("naptan_id", id);
+            properties.insert("name", name);
+            properties.insert("marker-color", marker_color);
+            features.push(serde_json::to_value(&feature("Point", (lon, lat), properties)));
+        }
+    }
+
+    FeatureCollection {
+        kind: "FeatureCollection",
+        features: features,
+    }
+}
+
+/// Write one GeoJSON FeatureCollection per line into the GeoJSON directory
+pub fn write_geojson(lines: &[Line], options: &Options) {
+    let geojson_path = Path::new(&options.geojson_dir);
+    let _ = fs::create_dir_all(geojson_path);
+
+    for line in lines {
+        let fpath = geojson_path.join(format!("{}.geojson", line.id));
+        let mut f = File::create(fpath).unwrap();
+        serde_json::to_writer(&mut f, &line_features(line, options)).unwrap();
+    }
+}
+
+#[test]
+fn line_features_test() {
+    use tfl::line::{Direction, Mode, RouteSection, Sequence, Stop};
+
+    let stop = |id: &str, lon: f64| Stop {
+        naptan_id: id.to_owned(),
+        common_name: id.to_owned(),
+        lat: 51.5,
+        lon: lon,
+        modes: vec![],
+        children: vec![],
+    };
+    let section = |originator: &str, destination: &str, direction: Direction| RouteSection {
+        name: format!("{} to {}", originator, destination),
+        direction: direction,
+        originator: originator.to_owned(),
+        origination_name: None,
+        destination: destination.to_owned(),
+        destination_name: None,
+        timetable: None,
+    };
+    // only the outbound section has a line string to be shaped from
+    let line = Line {
+        id: "1".to_owned(),
+        name: "1".to_owned(),
+        mode: Mode::Bus,
+        route_sections: vec![section("a", "b", Direction::Outbound), section("b", "a", Direction::Inbound)],
+        stops: Some(vec![stop("a", -0.12), stop("b", -0.10)]),
+        inbound_sequence: None,
+        outbound_sequence: Some(Sequence {
+            line_strings: vec!["[[-0.12,51.5],[-0.11,51.501],[-0.10,51.5]]".to_owned()],
+            stations: vec![],
+            stop_point_sequences: vec![],
+            ordered_line_routes: vec![],
+        }),
+    };
+    let collection = line_features(&line, &Options::default());
+    let kind = |feature: &Value| feature.lookup("geometry.type").and_then(|x| x.as_string()).unwrap().to_owned();
+    let shapes: Vec<&Value> = collection.features.iter().filter(|x| kind(x) == "LineString").collect();
+
+    assert_eq!(shapes.len(), 1);
+    assert_eq!(collection.features.iter().filter(|x| kind(x) == "Point").count(), 2);
+
+    let property = |name: &str| shapes[0].lookup(&format!("properties.{}", name)).and_then(|x| x.as_string()).map(|x| x.to_owned());
+    assert_eq!(property("shape_id"), Some("1 a to b".to_owned()));
+    assert_eq!(property("mode"), Some("bus".to_owned()));
+    assert_eq!(property("direction"), Some("outbound".to_owned()));
+    assert_eq!(property("stroke"), Some("#DC241F".to_owned()));
+
+    let coordinates: Vec<Vec<f64>> = shapes[0].lookup("geometry.coordinates").and_then(|x| x.as_array()).unwrap().iter()
+        .map(|pt| pt.as_array().unwrap().iter().map(|x| x.as_f64().unwrap()).collect())
+        .collect();
+    assert_eq!(coordinates, vec![vec![-0.12, 51.5], vec![-0.11, 51.501], vec![-0.10, 51.5]]);
+}
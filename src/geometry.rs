@@ -1,27 +1,27 @@
-use serde_json;
-use std::collections::{HashSet, HashMap};
-use std::f64::consts::PI;
+use serde_json::{self, Value};
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashMap};
+use std::f64;
 use std::fmt;
 
+use geodesy::{haversine, path_distances, path_length, project_onto_polyline, Projection};
 
-const PRECISION: f64 = 10000.0;
 
-/// Point containing latitude and longitude values as integers.
-/// Integers are used here due to Rust itself not providing some basic
-/// floating point functionality at the moment. All due to the undefined
-/// behavior revolving around `NaN` float values. For example, what is the boolean
-/// result of `0.0 > NaN` ? Its undefined, so therefore > is not implemented by
-/// Rust Proper! Perhaps they'll fix this massive inconvienence in the future.
-/// TODO use floats
-#[derive(PartialEq, Eq, Clone, Debug, Hash, Copy)]
-pub struct Point {
-    lat: i64,
-    lon: i64,
-}
+/// Approximate length in metres of one degree of latitude
+pub const METRES_PER_DEGREE: f64 = 111320.0;
+
+/// Spatial index grid cell size in degrees, roughly a kilometre in London
+const CELL_SIZE: f64 = 0.01;
+
+/// Default distance in metres under which path endpoints are joined into a
+/// single graph vertex
+pub const DEFAULT_SNAP_TOLERANCE: f64 = 15.0;
 
-/// Degress to Radians
-fn deg2rad(deg: f64) -> f64 {
-    ((2.0 * PI) / 180.0) * deg
+/// Point containing latitude and longitude values at full precision
+#[derive(PartialEq, Clone, Debug, Copy)]
+pub struct Point {
+    lat: f64,
+    lon: f64,
 }
 
 impl fmt::Display for Point {
@@ -31,41 +31,27 @@ impl fmt::Display for Point {
 }
 
 impl Point {
-    /// New integer stored Point from floating point lat/lon coordinates
+    /// New Point from lat/lon coordinates
     pub fn new(lat: f64, lon: f64) -> Point {
         Point {
-            lat: (lat * PRECISION).floor() as i64,
-            lon: (lon * PRECISION).floor() as i64,
+            lat: lat,
+            lon: lon,
         }
     }
 
     /// Latitude value
     pub fn lat(&self) -> f64 {
-        (self.lat as f64) / PRECISION
+        self.lat
     }
 
     /// Longitude value
     pub fn lon(&self) -> f64 {
-        (self.lon as f64) / PRECISION
+        self.lon
     }
 
-    /// Spheroid distance calculation given earth coordinates as lat/lon values.
-    /// Returns the distance in meters.
+    /// Great circle distance to another point in metres
     pub fn geo_distance(&self, p: &Point) -> f64 {
-        let r = 6371000.0; // metres
-        let lat1 = p.lat();
-        let lon1 = p.lon();
-        let lat2 = self.lat();
-        let lon2 = self.lon();
-        let sig1 = deg2rad(lat1);
-        let sig2 = deg2rad(lat2);
-        let deltasig = deg2rad(lat2 - lat1);
-        let deltalambda = deg2rad(lon2 - lon1);
-        let a = (deltasig / 2.0).sin() * (deltasig / 2.0).sin() +
-            sig1.cos() * sig2.cos() *
-            (deltalambda / 2.0).sin() * (deltalambda / 2.0).sin();
-        let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
-        r * c
+        haversine(self, p)
     }
 
 }
@@ -73,44 +59,421 @@ impl Point {
 /// Path
 pub type Path = Vec<Point>;
 
-/// Maintains a sparse routing graph
-#[derive(Default)]
+/// Uniform latitude/longitude grid of points with attached values,
+/// answering nearest, k-nearest and radius queries without scanning every
+/// point.
+pub struct SpatialIndex<T> {
+    cells: HashMap<(i64, i64), Vec<(Point, T)>>,
+    /// Lowest and highest occupied cells
+    bounds: Option<((i64, i64), (i64, i64))>,
+}
+
+impl<T> Default for SpatialIndex<T> {
+    fn default() -> SpatialIndex<T> {
+        SpatialIndex::new()
+    }
+}
+
+fn grid_cell(pt: &Point) -> (i64, i64) {
+    ((pt.lat() / CELL_SIZE).floor() as i64, (pt.lon() / CELL_SIZE).floor() as i64)
+}
+
+impl<T> SpatialIndex<T> {
+    /// New empty index
+    pub fn new() -> SpatialIndex<T> {
+        SpatialIndex {
+            cells: HashMap::new(),
+            bounds: None,
+        }
+    }
+
+    /// Add a point with its value
+    pub fn insert(&mut self, pt: Point, value: T) {
+        let cell = grid_cell(&pt);
+        self.bounds = Some(match self.bounds {
+            Some(((min_x, min_y), (max_x, max_y))) => {
+                ((min_x.min(cell.0), min_y.min(cell.1)), (max_x.max(cell.0), max_y.max(cell.1)))
+            },
+            None => (cell, cell),
+        });
+        self.cells.entry(cell).or_insert_with(Vec::new).push((pt, value));
+    }
+
+    /// Entries of the cells forming the square ring at distance r of center
+    fn ring_entries(&self, center: (i64, i64), r: i64) -> Vec<&(Point, T)> {
+        let mut entries = Vec::new();
+        for x in center.0 - r .. center.0 + r + 1 {
+            for y in center.1 - r .. center.1 + r + 1 {
+                if (x - center.0).abs() == r || (y - center.1).abs() == r {
+                    if let Some(cell_entries) = self.cells.get(&(x, y)) {
+                        entries.extend(cell_entries.iter());
+                    }
+                }
+            }
+        }
+        entries
+    }
+
+    /// Number of rings around center needed to cover every occupied cell
+    fn max_ring(&self, center: (i64, i64)) -> i64 {
+        match self.bounds {
+            Some(((min_x, min_y), (max_x, max_y))) => {
+                *[center.0 - min_x, max_x - center.0, center.1 - min_y, max_y - center.1].iter().max().unwrap()
+            },
+            None => -1,
+        }
+    }
+
+    /// The k nearest points to pt with their values and distances in
+    /// metres, closest first
+    pub fn k_nearest(&self, pt: &Point, k: usize) -> Vec<(Point, &T, f64)> {
+        let mut found: Vec<(Point, &T, f64)> = Vec::new();
+        if k == 0 {
+            return found;
+        }
+
+        let center = grid_cell(pt);
+        let cell_metres = CELL_SIZE * METRES_PER_DEGREE * pt.lat().to_radians().cos();
+
+        for r in 0 .. self.max_ring(center) + 1 {
+            for &(ref point, ref value) in self.ring_entries(center, r) {
+                found.push((*point, value, point.geo_distance(pt)));
+            }
+            found.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+
+            // anything beyond this ring is at least r cells away
+            if found.len() >= k && found[k - 1].2 <= r as f64 * cell_metres {
+                break;
+            }
+        }
+
+        found.truncate(k);
+        found
+    }
+
+    /// The nearest point to pt with its value and distance in metres
+    pub fn nearest(&self, pt: &Point) -> Option<(Point, &T, f64)> {
+        self.k_nearest(pt, 1).into_iter().next()
+    }
+
+    /// All points within radius metres of pt with their values and distances
+    pub fn within(&self, pt: &Point, radius: f64) -> Vec<(Point, &T, f64)> {
+        let mut found: Vec<(Point, &T, f64)> = Vec::new();
+        let center = grid_cell(pt);
+        let cell_metres = CELL_SIZE * METRES_PER_DEGREE * pt.lat().to_radians().cos();
+        let rings = (radius / cell_metres).ceil() as i64;
+
+        for r in 0 .. rings.min(self.max_ring(center)) + 1 {
+            for &(ref point, ref value) in self.ring_entries(center, r) {
+                let distance = point.geo_distance(pt);
+                if distance <= radius {
+                    found.push((*point, value, distance));
+                }
+            }
+        }
+
+        found
+    }
+}
+
+/// Maintains a sparse routing graph. Path endpoints closer than the snapping
+/// tolerance share a vertex, vertices are referred to by their index.
 pub struct RouteGraph {
-    vertices: HashSet<Point>,
-    edges: HashMap<Point, Vec<Point>>,
-    paths: HashMap<(Point, Point), Path>,
+    tolerance: f64,
+    vertices: Vec<Point>,
+    index: SpatialIndex<usize>,
+    /// Segment midpoints of the paths leaving their lower vertex, with the
+    /// key of their path
+    segments: SpatialIndex<(usize, usize)>,
+    /// Length in metres of the longest indexed segment
+    max_segment: f64,
+    edges: HashMap<usize, Vec<usize>>,
+    paths: HashMap<(usize, usize), Path>,
+    /// Cumulative distances along the paths leaving their lower vertex
+    distances: HashMap<(usize, usize), Vec<f64>>,
+    lengths: HashMap<(usize, usize), f64>,
+}
+
+impl Default for RouteGraph {
+    fn default() -> RouteGraph {
+        RouteGraph::new()
+    }
+}
+
+/// Maximum distance in metres between a point and the graph paths it is
+/// projected onto
+const MAX_PROJECTION_OFFSET: f64 = 2000.0;
+
+/// Projection of a point onto the path joining two vertices
+struct PathProjection {
+    key: (usize, usize),
+    projection: Projection,
+}
+
+/// Search state of the shortest path search, ordered so the binary heap
+/// pops the lowest cost first
+#[derive(Copy, Clone, PartialEq)]
+struct SearchState {
+    cost: f64,
+    vertex: usize,
+}
+
+impl Eq for SearchState {}
+
+impl Ord for SearchState {
+    fn cmp(&self, other: &SearchState) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for SearchState {
+    fn partial_cmp(&self, other: &SearchState) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Outcome of decoding the lineStrings of a line
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LineStringDiagnostics {
+    /// Number of line strings decoded
+    pub line_strings: usize,
+    /// Number of usable paths found
+    pub paths: usize,
+    /// Number of empty or single point paths which were skipped
+    pub skipped: usize,
+    /// Number of gaps bridged while stitching fragments together
+    pub gaps_closed: usize,
+    /// Line strings which could not be decoded, with the reason
+    pub errors: Vec<String>,
+}
+
+impl LineStringDiagnostics {
+    /// Add the counts of other diagnostics to these
+    pub fn merge(&mut self, other: LineStringDiagnostics) {
+        self.line_strings += other.line_strings;
+        self.paths += other.paths;
+        self.skipped += other.skipped;
+        self.gaps_closed += other.gaps_closed;
+        self.errors.extend(other.errors);
+    }
+
+    /// Whether anything was skipped or failed to decode
+    pub fn has_problems(&self) -> bool {
+        self.skipped > 0 || !self.errors.is_empty()
+    }
+}
+
+/// A [lon, lat] position
+fn position(value: &Value) -> Option<Point> {
+    match *value {
+        Value::Array(ref xs) if xs.len() >= 2 => {
+            match (xs[0].as_f64(), xs[1].as_f64()) {
+                (Some(lon), Some(lat)) => Some(Point::new(lat, lon)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Recursively collect the paths of a decoded line string, which may be a
+/// single path, nested arrays of paths, a GeoJSON geometry or a JSON encoded
+/// string of any of those.
+fn collect_paths(value: &Value, paths: &mut Vec<Path>, diagnostics: &mut LineStringDiagnostics) -> Result<(), String> {
+    match *value {
+        Value::Object(ref map) => {
+            match map.get("coordinates") {
+                Some(coordinates) => collect_paths(coordinates, paths, diagnostics),
+                None => Err(format!("object without coordinates {}", value)),
+            }
+        },
+        Value::Array(ref xs) => {
+            if xs.is_empty() || (position(value).is_some() && xs[0].as_f64().is_some()) {
+                // empty path or lone position
+                diagnostics.skipped += 1;
+                Ok(())
+            } else if position(&xs[0]).is_some() {
+                match xs.iter().map(position).collect::<Option<Path>>() {
+                    Some(ref path) if path.len() < 2 => {
+                        diagnostics.skipped += 1;
+                        Ok(())
+                    },
+                    Some(path) => {
+                        paths.push(path);
+                        Ok(())
+                    },
+                    None => Err(format!("invalid position in {}", value)),
+                }
+            } else {
+                for x in xs {
+                    try!(collect_paths(x, paths, diagnostics));
+                }
+                Ok(())
+            }
+        },
+        Value::String(ref body) => {
+            match serde_json::from_str::<Value>(body) {
+                Ok(ref decoded) => collect_paths(decoded, paths, diagnostics),
+                Err(err) => Err(format!("{}", err)),
+            }
+        },
+        _ => Err(format!("unexpected value {}", value)),
+    }
 }
 
 /// Convert the TFL lineStrings attribute to a simple flat vectory of paths.
 /// lineStrings in TFL data is a JSON array of string values, containing
-/// either an array of points or an array of arrays of points, we handle both.
-pub fn linestrings_to_paths(line_strings: &[String]) -> Vec<Path> {
+/// either an array of points or arbitrarily nested arrays of points, possibly
+/// wrapped in GeoJSON geometries. Paths with fewer than two points are
+/// skipped and undecodable line strings reported in the diagnostics.
+pub fn linestrings_to_paths(line_strings: &[String]) -> (Vec<Path>, LineStringDiagnostics) {
     let mut paths : Vec<Path> = Vec::new();
+    let mut diagnostics = LineStringDiagnostics::default();
+
     for line_string in line_strings {
-        match serde_json::from_str::<Vec<(f64, f64)>>(&line_string) {
-            Ok(raw_path) => paths.push(raw_path.iter().map(|&(lon, lat)| Point::new(lat, lon)).collect()),
-            Err(err) => {
-                match serde_json::from_str::<Vec<Vec<(f64, f64)>>>(&line_string) {
-                    Ok(raw_paths) => {
-                        for raw_path in raw_paths {
-                            paths.push(raw_path.iter().map(|&(lon, lat)| Point::new(lat, lon)).collect());
-                        }
-                    },
-                    Err(err2) => println!("Errors decoding line string, single line {}, multi line {}", err, err2),
+        diagnostics.line_strings += 1;
+        let decoded = serde_json::from_str::<Value>(line_string).map_err(|err| format!("{}", err));
+        let result = decoded.and_then(|value| collect_paths(&value, &mut paths, &mut diagnostics));
+
+        if let Err(err) = result {
+            diagnostics.errors.push(err);
+        }
+    }
+
+    diagnostics.paths = paths.len();
+    (paths, diagnostics)
+}
+
+/// Endpoint of a path
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum End {
+    Head,
+    Tail,
+}
+
+impl End {
+    fn index(&self) -> usize {
+        match *self {
+            End::Head => 0,
+            End::Tail => 1,
+        }
+    }
+
+    fn other(&self) -> End {
+        match *self {
+            End::Head => End::Tail,
+            End::Tail => End::Head,
+        }
+    }
+}
+
+fn end_point(path: &Path, end: End) -> Point {
+    match end {
+        End::Head => path[0],
+        End::Tail => path[path.len() - 1],
+    }
+}
+
+/// Join path fragments whose endpoints are within tolerance metres of each
+/// other into continuous paths, reversing fragments as needed. Only
+/// unambiguous endpoints, close to exactly one other endpoint, are joined so
+/// branch junctions are kept intact. Returns the stitched paths and the
+/// number of gaps bridged between endpoints which did not coincide.
+pub fn stitch_paths(paths: Vec<Path>, tolerance: f64) -> (Vec<Path>, usize) {
+    let paths: Vec<Path> = paths.into_iter().filter(|path| path.len() >= 2).collect();
+    let mut index = SpatialIndex::new();
+
+    for (i, path) in paths.iter().enumerate() {
+        index.insert(end_point(path, End::Head), (i, End::Head));
+        index.insert(end_point(path, End::Tail), (i, End::Tail));
+    }
+
+    let neighbour = |i: usize, end: End| -> Option<(usize, End)> {
+        let candidates: Vec<(usize, End)> = index.within(&end_point(&paths[i], end), tolerance)
+                                                 .into_iter()
+                                                 .map(|(_, &candidate, _)| candidate)
+                                                 .filter(|&(j, _)| j != i)
+                                                 .collect();
+        if candidates.len() == 1 { Some(candidates[0]) } else { None }
+    };
+
+    // mutual links between the head and tail of each path and another path
+    let mut links: Vec<[Option<(usize, End)>; 2]> = vec![[None, None]; paths.len()];
+    for i in 0..paths.len() {
+        for &end in &[End::Head, End::Tail] {
+            if let Some((j, other_end)) = neighbour(i, end) {
+                if neighbour(j, other_end) == Some((i, end)) {
+                    links[i][end.index()] = Some((j, other_end));
                 }
-            },
+            }
+        }
+    }
+
+    let mut visited = vec![false; paths.len()];
+    let mut stitched = Vec::new();
+    let mut gaps = 0;
+
+    // walk chains from their free ends first, leaving only cycles
+    let free: Vec<usize> = (0..paths.len()).filter(|&i| links[i][0].is_none() || links[i][1].is_none()).collect();
+    for start in free.into_iter().chain(0..paths.len()) {
+        if visited[start] {
+            continue;
         }
+        visited[start] = true;
+
+        let mut chain = paths[start].clone();
+        let mut next = if links[start][End::Head.index()].is_none() {
+            links[start][End::Tail.index()]
+        } else if links[start][End::Tail.index()].is_none() {
+            chain.reverse();
+            links[start][End::Head.index()]
+        } else {
+            links[start][End::Tail.index()]
+        };
+
+        while let Some((j, entry)) = next {
+            if visited[j] {
+                break;
+            }
+            visited[j] = true;
+
+            let mut fragment = paths[j].clone();
+            if entry == End::Tail {
+                fragment.reverse();
+            }
+            if chain[chain.len() - 1] == fragment[0] {
+                fragment.remove(0);
+            } else {
+                gaps += 1;
+            }
+            chain.extend(fragment);
+            next = links[j][entry.other().index()];
+        }
+
+        stitched.push(chain);
     }
-    paths
+
+    (stitched, gaps)
 }
 
 impl RouteGraph {
-    /// New Route Graph
+    /// New Route Graph with the default snapping tolerance
     pub fn new() -> RouteGraph {
+        RouteGraph::with_tolerance(DEFAULT_SNAP_TOLERANCE)
+    }
+
+    /// New Route Graph joining path endpoints within tolerance metres
+    pub fn with_tolerance(tolerance: f64) -> RouteGraph {
         RouteGraph {
-            vertices: HashSet::new(),
+            tolerance: tolerance,
+            vertices: Vec::new(),
+            index: SpatialIndex::new(),
+            segments: SpatialIndex::new(),
+            max_segment: 0.0,
             edges: HashMap::new(),
             paths: HashMap::new(),
+            distances: HashMap::new(),
+            lengths: HashMap::new(),
         }
     }
 
@@ -121,86 +484,427 @@ impl RouteGraph {
         }
     }
 
-    /// Add single path to the graph
+    /// Vertex for a path endpoint, reusing an existing vertex within the
+    /// snapping tolerance
+    fn vertex(&mut self, pt: Point) -> usize {
+        let snapped = match self.index.nearest(&pt) {
+            Some((_, &vertex, dist)) if dist <= self.tolerance => Some(vertex),
+            _ => None,
+        };
+
+        match snapped {
+            Some(vertex) => vertex,
+            None => {
+                let vertex = self.vertices.len();
+                self.vertices.push(pt);
+                self.index.insert(pt, vertex);
+                vertex
+            },
+        }
+    }
+
+    /// Add single path to the graph. When two paths join the same vertices
+    /// only the shortest one is kept, paths with fewer than two points are
+    /// ignored.
     pub fn add_path(&mut self, path: &Path) {
+        if path.len() < 2 {
+            return;
+        }
+
         // add points
-        let first = path.first().unwrap();
-        let last = path.last().unwrap();
-        self.vertices.insert(*first);
-        self.vertices.insert(*last);
+        let first = self.vertex(*path.first().unwrap());
+        let last = self.vertex(*path.last().unwrap());
 
         // add bidirectional edges
-        self.edges.entry(*first).or_insert_with(|| vec![*last]).push(*last);
-        self.edges.entry(*last).or_insert_with(|| vec![*first]).push(*first);
+        for &(from, to) in &[(first, last), (last, first)] {
+            let to_vertices = self.edges.entry(from).or_insert_with(Vec::new);
+            if !to_vertices.contains(&to) {
+                to_vertices.push(to);
+            }
+        }
 
         // add paths
-        self.paths.insert((*first, *last), path.clone());
-        self.paths.insert((*last, *first), path.iter().rev().cloned().collect());
-        assert!(self.paths.contains_key(&(first.clone(), last.clone())));
-        assert!(self.paths.contains_key(&(last.clone(), first.clone())));
-    }
-
-    /// Find the closest actual point (vertex) in our graph since they are not
-    /// going to be exact matches
-    fn closest_point(&self, pt: &Point) -> (Point, f64) {
-        let far_pt = Point::new(pt.lat() + 90.0, pt.lon() + 90.0);
-        let far_dist = far_pt.geo_distance(pt);
-        let (min_pt, min_dist) = self.edges.keys().fold((&far_pt, far_dist), |(min_pt, min_dist), vert| {
-            let vert_dist = vert.geo_distance(pt);
-            if vert_dist < min_dist {
-                (vert, vert_dist)
-            } else {
-                (min_pt, min_dist)
+        let length = path_length(path);
+        let shorter = self.lengths.get(&(first, last)).map_or(true, |&x| length < x);
+        if shorter {
+            let key = (cmp::min(first, last), cmp::max(first, last));
+            for pair in path.windows(2) {
+                let midpoint = Point::new((pair[0].lat() + pair[1].lat()) / 2.0, (pair[0].lon() + pair[1].lon()) / 2.0);
+                self.segments.insert(midpoint, key);
+                self.max_segment = self.max_segment.max(pair[0].geo_distance(&pair[1]));
             }
+            self.paths.insert((first, last), path.clone());
+            self.lengths.insert((first, last), length);
+            // the reverse of a loop has the same key and would replace it
+            if first != last {
+                self.paths.insert((last, first), path.iter().rev().cloned().collect());
+                self.lengths.insert((last, first), length);
+            }
+            let distances = path_distances(&self.paths[&key]);
+            self.distances.insert(key, distances);
+        }
+    }
+
+    /// Projections of a point onto the graph paths within the maximum
+    /// offset, closest first. Paths are stored in both directions so only
+    /// the one leaving the lower vertex is projected onto. A segment within
+    /// the maximum offset has its midpoint within half the longest segment
+    /// further, so only the paths of those midpoints are candidates.
+    fn projections(&self, pt: &Point) -> Vec<PathProjection> {
+        let radius = MAX_PROJECTION_OFFSET + self.max_segment / 2.0;
+        let mut keys: Vec<(usize, usize)> = self.segments.within(pt, radius).into_iter().map(|(_, &key, _)| key).collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut projections = Vec::new();
+        for key in keys {
+            if let Some(projection) = project_onto_polyline(&self.paths[&key], &self.distances[&key], pt, 0) {
+                if projection.offset <= MAX_PROJECTION_OFFSET {
+                    projections.push(PathProjection { key: key, projection: projection });
+                }
+            }
+        }
+        projections.sort_by(|a, b| match a.projection.offset.partial_cmp(&b.projection.offset) {
+            Some(Ordering::Equal) | None => a.key.cmp(&b.key),
+            Some(ordering) => ordering,
         });
+        projections
+    }
 
-        (*min_pt, min_dist)
+    /// Path which both points project onto within the snapping tolerance of
+    /// their closest projections, paths are often drawn over each other
+    fn shared_path<'a>(&self, starts: &'a [PathProjection], ends: &'a [PathProjection]) -> Option<(&'a PathProjection, &'a PathProjection)> {
+        let mut best = None;
+        let mut best_offset = f64::MAX;
+        let start_limit = starts[0].projection.offset + self.tolerance;
+        let end_limit = ends[0].projection.offset + self.tolerance;
+
+        for start in starts.iter().take_while(|x| x.projection.offset <= start_limit) {
+            for end in ends.iter().take_while(|x| x.projection.offset <= end_limit) {
+                let offset = start.projection.offset + end.projection.offset;
+                if start.key == end.key && offset < best_offset {
+                    best = Some((start, end));
+                    best_offset = offset;
+                }
+            }
+        }
+        best
     }
 
-    /// Find a path between two points if one exists
+    /// Find a path between two points if one exists. Both points are
+    /// projected onto the graph paths so they may lie anywhere along them,
+    /// the path runs between their projections and has at least two points.
     pub fn path(&self, p0: Point, p1: Point) -> Option<Path> {
-        let (start, start_dist) = self.closest_point(&p0);
-        if start_dist > 2000.0 {
-            println!("start point {} to closest {} distance is {} > 2000", p0, start, start_dist);
+        let starts = self.projections(&p0);
+        if starts.is_empty() {
+            warn!("start point {} is more than {} metres from the graph", p0, MAX_PROJECTION_OFFSET);
             return None;
         }
-        let (end, end_dist) = self.closest_point(&p1);
-        if end_dist > 2000.0 {
-            println!("end point {} to closest {} distance is {} > 2000", p1, end, end_dist);
+        let ends = self.projections(&p1);
+        if ends.is_empty() {
+            warn!("end point {} is more than {} metres from the graph", p1, MAX_PROJECTION_OFFSET);
             return None;
         }
-        let visited: HashSet<Point> = HashSet::new();
-        self.find_path(start, end, &visited)
-    }
-
-    // Recursively search the path graph from p0 to p1, when a path is found
-    // the callstack will inherently build up a single Path containing all the
-    // points between the two.
-    // Visited is a simple HashSet marking points we've already visited to avoid
-    // infinitely recursing in circles.
-    fn find_path(&self, p0: Point, p1: Point, visited: &HashSet<Point>) -> Option<Path> {
-        match self.edges.get(&p0) {
-            Some(to_vertices) => {
-                if visited.contains(&p0) {
-                    None
-                } else {
-                    for next in to_vertices {
-                        if *next == p1 {
-                            return Some(self.paths.get(&(p0, *next)).unwrap().clone());
-                        }
-
-                        let mut visited0 = visited.clone();
-                        visited0.insert(*next);
-
-                        if let Some(path) = self.find_path(*next, p1, &visited0) {
-                            return Some(vec![&(self.paths.get(&(p0, *next)).unwrap())[..], &path[..]].concat());
-                        }
-                    }
 
-                    None
-                }
+        let mut path = match self.shared_path(&starts, &ends) {
+            Some((start, end)) => sub_path(&self.paths[&start.key], &start.projection, &end.projection),
+            None => match self.search_path(&starts[0], &ends[0]) {
+                Some(path) => path,
+                None => return None,
             },
-            None => None,
+        };
+        path.dedup();
+
+        if path.len() < 2 {
+            None
+        } else {
+            Some(path)
+        }
+    }
+
+    /// Path leaving the start projection towards either end of its path,
+    /// through the graph, and entering the path of the end projection from
+    /// either end, whichever is shortest. Returns None when both paths are
+    /// in disconnected components of the graph.
+    fn search_path(&self, start: &PathProjection, end: &PathProjection) -> Option<Path> {
+        let start_path = &self.paths[&start.key];
+        let end_path = &self.paths[&end.key];
+        let start_along = start.projection.distance_along;
+        let end_along = end.projection.distance_along;
+        let exits = [(start.key.0, start_along), (start.key.1, self.lengths[&start.key] - start_along)];
+        let entries = [(end.key.0, end_along), (end.key.1, self.lengths[&end.key] - end_along)];
+
+        let (costs, previous) = self.search(&exits);
+        let mut entry: Option<(usize, f64)> = None;
+        for (i, &(vertex, distance)) in entries.iter().enumerate() {
+            if let Some(&cost) = costs.get(&vertex) {
+                if entry.map_or(true, |(_, best)| cost + distance < best) {
+                    entry = Some((i, cost + distance));
+                }
+            }
+        }
+        let entry = match entry {
+            Some((i, _)) => i,
+            None => return None,
+        };
+
+        let mut vertices = vec![entries[entry].0];
+        while let Some(&vertex) = previous.get(&vertices[vertices.len() - 1]) {
+            vertices.push(vertex);
+        }
+        vertices.reverse();
+
+        // a loop path leaves its vertex both ways, take the shorter one
+        let exit = if exits[0].0 == vertices[0] && (exits[1].0 != vertices[0] || exits[0].1 <= exits[1].1) { 0 } else { 1 };
+        let mut path: Path = if exit == 0 {
+            head(start_path, &start.projection).into_iter().rev().collect()
+        } else {
+            tail(start_path, &start.projection)
+        };
+
+        // consecutive pieces share their joining vertex
+        for pair in vertices.windows(2) {
+            path.extend(self.paths[&(pair[0], pair[1])].iter().skip(1).cloned());
+        }
+        if entry == 0 {
+            path.extend(head(end_path, &end.projection).into_iter().skip(1));
+        } else {
+            path.extend(tail(end_path, &end.projection).into_iter().rev().skip(1));
+        }
+        Some(path)
+    }
+
+    // Dijkstra search over the graph vertices weighted by the length of the
+    // paths joining them, starting from several vertices at given costs.
+    // Returns the cost of every reachable vertex and the vertex it is
+    // reached from.
+    fn search(&self, sources: &[(usize, f64)]) -> (HashMap<usize, f64>, HashMap<usize, usize>) {
+        let mut costs: HashMap<usize, f64> = HashMap::new();
+        let mut previous: HashMap<usize, usize> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+
+        for &(vertex, cost) in sources {
+            if cost < *costs.get(&vertex).unwrap_or(&f64::MAX) {
+                costs.insert(vertex, cost);
+                heap.push(SearchState { cost: cost, vertex: vertex });
+            }
         }
+
+        while let Some(SearchState { cost, vertex }) = heap.pop() {
+            if cost > *costs.get(&vertex).unwrap_or(&f64::MAX) {
+                continue;
+            }
+            if let Some(to_vertices) = self.edges.get(&vertex) {
+                for next in to_vertices {
+                    let next_cost = cost + self.lengths[&(vertex, *next)];
+                    if next_cost < *costs.get(next).unwrap_or(&f64::MAX) {
+                        costs.insert(*next, next_cost);
+                        previous.insert(*next, vertex);
+                        heap.push(SearchState { cost: next_cost, vertex: *next });
+                    }
+                }
+            }
+        }
+
+        (costs, previous)
     }
 }
+
+/// Projected point, or the path point it falls on so shapes keep the
+/// original coordinates
+fn projected_point(path: &[Point], projection: &Projection) -> Point {
+    let segment_end = cmp::min(projection.segment + 1, path.len() - 1);
+    if projection.point.geo_distance(&path[projection.segment]) < 0.01 {
+        path[projection.segment]
+    } else if projection.point.geo_distance(&path[segment_end]) < 0.01 {
+        path[segment_end]
+    } else {
+        projection.point
+    }
+}
+
+/// Part of a path from its first point to a projection
+fn head(path: &[Point], projection: &Projection) -> Path {
+    let mut points = path[.. projection.segment + 1].to_vec();
+    points.push(projected_point(path, projection));
+    points
+}
+
+/// Part of a path from a projection to its last point
+fn tail(path: &[Point], projection: &Projection) -> Path {
+    let mut points = vec![projected_point(path, projection)];
+    points.extend(path[projection.segment + 1 ..].iter().cloned());
+    points
+}
+
+/// Part of a path between two projections onto it, reversed when `to` comes
+/// before `from`
+fn sub_path(path: &[Point], from: &Projection, to: &Projection) -> Path {
+    if to.distance_along < from.distance_along {
+        let mut points = sub_path(path, to, from);
+        points.reverse();
+        return points;
+    }
+
+    let mut points = vec![projected_point(path, from)];
+    if to.segment > from.segment {
+        points.extend(path[from.segment + 1 .. to.segment + 1].iter().cloned());
+    }
+    points.push(projected_point(path, to));
+    points
+}
+
+#[test]
+fn shortest_path_test() {
+    let a = Point::new(51.5000, -0.1000);
+    let b = Point::new(51.5000, -0.1100);
+    let c = Point::new(51.5050, -0.1050);
+    let d = Point::new(51.5100, -0.1050);
+    let mut graph = RouteGraph::new();
+
+    graph.add_paths(&[vec![a, d, c], vec![c, b], vec![a, c]]);
+
+    assert_eq!(graph.path(a, b), Some(vec![a, c, b]));
+    assert_eq!(graph.path(b, a), Some(vec![b, c, a]));
+}
+
+#[test]
+fn snapping_test() {
+    let a = Point::new(51.50001, -0.10001);
+    let b = Point::new(51.50004, -0.10003);
+    let c = Point::new(51.5050, -0.1050);
+    let d = Point::new(51.5100, -0.1050);
+    let mut graph = RouteGraph::with_tolerance(15.0);
+
+    graph.add_paths(&[vec![c, a], vec![b, d]]);
+
+    assert_eq!(graph.vertices.len(), 3);
+    assert_eq!(graph.path(c, d), Some(vec![c, a, d]));
+}
+
+#[test]
+fn mid_path_section_test() {
+    let a = Point::new(51.5000, -0.3000);
+    let b = Point::new(51.5000, -0.2000);
+    let c = Point::new(51.5000, -0.1000);
+    let d = Point::new(51.5500, -0.1000);
+    let mut graph = RouteGraph::new();
+
+    graph.add_paths(&[vec![a, b, c], vec![c, d]]);
+
+    // both stops are kilometres away from the path ends
+    let s1 = Point::new(51.5001, -0.2500);
+    let s2 = Point::new(51.5001, -0.1500);
+    let s3 = Point::new(51.5250, -0.0999);
+
+    let path = graph.path(s1, s2).unwrap();
+    assert_eq!(path.len(), 3);
+    assert!(path[0].geo_distance(&Point::new(51.5000, -0.2500)) < 1.0);
+    assert_eq!(path[1], b);
+    assert!(path[2].geo_distance(&Point::new(51.5000, -0.1500)) < 1.0);
+
+    let reversed = graph.path(s2, s1).unwrap();
+    assert_eq!(reversed, path.into_iter().rev().collect::<Vec<_>>());
+
+    let path = graph.path(s1, s3).unwrap();
+    assert_eq!(path.len(), 4);
+    assert_eq!(&path[1 .. 3], &[b, c]);
+    assert!(path[3].geo_distance(&Point::new(51.5250, -0.1000)) < 1.0);
+
+    // further than the maximum offset from every path
+    assert_eq!(graph.path(Point::new(51.5300, -0.2500), s2), None);
+}
+
+#[test]
+fn same_vertex_section_test() {
+    let a = Point::new(51.5000, -0.1200);
+    let b = Point::new(51.5000, -0.1000);
+    let c = Point::new(51.5200, -0.1000);
+    let mut graph = RouteGraph::new();
+
+    graph.add_paths(&[vec![a, b], vec![b, c]]);
+
+    // both stops are closest to vertex b but on different paths
+    let s1 = Point::new(51.5001, -0.1005);
+    let s2 = Point::new(51.5005, -0.0999);
+
+    let path = graph.path(s1, s2).unwrap();
+    assert_eq!(path.len(), 3);
+    assert_eq!(path[1], b);
+    assert_eq!(graph.path(s1, s1), None);
+}
+
+#[test]
+fn loop_path_test() {
+    let a = Point::new(51.5000, -0.1000);
+    let b = Point::new(51.5000, -0.1100);
+    let c = Point::new(51.5050, -0.1050);
+    let a2 = Point::new(51.50001, -0.10001);
+    let mut graph = RouteGraph::with_tolerance(15.0);
+
+    graph.add_path(&vec![a, b, c, a2]);
+
+    assert_eq!(graph.vertices.len(), 1);
+    assert_eq!(graph.paths[&(0, 0)], vec![a, b, c, a2]);
+}
+
+#[test]
+fn spatial_index_test() {
+    let mut index = SpatialIndex::new();
+    let points = [
+        Point::new(51.5400, -0.1410),
+        Point::new(51.5155, -0.1410),
+        Point::new(51.5050, -0.1410),
+        Point::new(51.4800, -0.1410),
+    ];
+
+    for (i, pt) in points.iter().enumerate() {
+        index.insert(*pt, i);
+    }
+
+    let query = Point::new(51.5150, -0.1410);
+    let nearest: Vec<usize> = index.k_nearest(&query, 2).iter().map(|&(_, &i, _)| i).collect();
+
+    assert_eq!(nearest, vec![1, 2]);
+    assert_eq!(index.nearest(&query).map(|(_, &i, _)| i), Some(1));
+    assert_eq!(index.within(&query, 500.0).len(), 1);
+}
+
+#[test]
+fn linestrings_to_paths_test() {
+    let line_strings = vec![
+        "[[-0.1,51.5],[-0.2,51.6]]".to_owned(),
+        "[[[-0.1,51.5],[-0.2,51.6]],[[-0.3,51.7]],[]]".to_owned(),
+        "{\"type\":\"MultiLineString\",\"coordinates\":[[[-0.1,51.5],[-0.2,51.6]]]}".to_owned(),
+        "\"[[-0.1,51.5],[-0.2,51.6]]\"".to_owned(),
+        "[[-0.1,51.5],[\"a\",51.6]]".to_owned(),
+        "not json".to_owned(),
+    ];
+    let (paths, diagnostics) = linestrings_to_paths(&line_strings);
+
+    assert_eq!(paths.len(), 4);
+    assert_eq!(paths[0], vec![Point::new(51.5, -0.1), Point::new(51.6, -0.2)]);
+    assert_eq!(diagnostics.line_strings, 6);
+    assert_eq!(diagnostics.paths, 4);
+    assert_eq!(diagnostics.skipped, 2);
+    assert_eq!(diagnostics.errors.len(), 2);
+}
+
+#[test]
+fn stitch_paths_test() {
+    let p0 = Point::new(51.5000, -0.1200);
+    let p1 = Point::new(51.5000, -0.1100);
+    let p2 = Point::new(51.50009, -0.1100);
+    let p3 = Point::new(51.5100, -0.1100);
+    let p4 = Point::new(51.5100, -0.1000);
+    let p5 = Point::new(51.5200, -0.1000);
+
+    let (paths, gaps) = stitch_paths(vec![vec![p0, p1], vec![p3, p2], vec![p3, p4]], 50.0);
+    assert_eq!(paths, vec![vec![p0, p1, p2, p3, p4]]);
+    assert_eq!(gaps, 1);
+
+    // three fragments meeting at a junction are left alone
+    let (paths, gaps) = stitch_paths(vec![vec![p0, p1], vec![p1, p3], vec![p1, p5]], 50.0);
+    assert_eq!(paths.len(), 3);
+    assert_eq!(gaps, 0);
+}
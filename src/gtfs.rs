@@ -3,193 +3,380 @@ use csv;
 use crypto::digest::Digest;
 use crypto::md5::Md5;
 use std::collections::{HashSet, HashMap};
+use scoped_threadpool::Pool;
 use std::fs::File;
 use std::fs;
+use std::io::Write;
 use std::path::Path;
+use std::str::FromStr;
+
+use agency::{line_agency, Agency};
+use colors::ColorTable;
+use tfl::line::{Direction, Line, Mode, TimeTable, RouteSection, Schedule, Sequence, KnownJourney, StationInterval};
+use frequencies::{journey_runs, seconds_fmt, FrequencyOptions};
+use geodesy::{path_distances, project_onto_polyline};
+use geometry::{linestrings_to_paths, stitch_paths, LineStringDiagnostics, RouteGraph, Point, DEFAULT_SNAP_TOLERANCE};
+use route_names::RouteNameTemplates;
+use route_types::RouteTypes;
+use parallel::parallel_map;
+use report::LineReport;
+use patterns::{has_timetable, section_pattern};
+use shape::{process_shape, ShapeOptions};
+use transfers::{transfers, TransferOptions};
+
+/// Default distance in metres under which line string fragments are stitched
+pub const DEFAULT_STITCH_TOLERANCE: f64 = 50.0;
+
+/// Dates of calendar.txt as YYYYMMDD
+#[derive(Clone, Debug, Deserialize)]
+pub struct CalendarWindow {
+    pub start_date: String,
+    pub end_date: String,
+}
+
+impl Default for CalendarWindow {
+    fn default() -> CalendarWindow {
+        CalendarWindow {
+            start_date: String::from("20151031"),
+            end_date: String::from("20161031"),
+        }
+    }
+}
+
+/// How trip ids are built from the line, route section, schedule and
+/// departure time of a trip
+#[derive(Clone, Debug, PartialEq)]
+pub enum TripIdScheme {
+    /// MD5 digest, stable and opaque
+    Hash,
+    /// The parts joined with dashes, longer but human readable
+    Readable,
+}
+
+impl FromStr for TripIdScheme {
+    type Err = String;
 
-use tfl::line::{Line, TimeTable, RouteSection, Schedule, KnownJourney, StationInterval};
-use geometry::{linestrings_to_paths, RouteGraph, Point};
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "hash" => Ok(TripIdScheme::Hash),
+            "readable" => Ok(TripIdScheme::Readable),
+            _ => Err(format!("unknown trip id scheme \"{}\", expected hash or readable", value)),
+        }
+    }
+}
 
-struct Route<'a> {
+/// GTFS generation settings
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Compact constant headway journeys into frequencies.txt when set
+    pub frequencies: Option<FrequencyOptions>,
+    /// Generate transfers.txt when set
+    pub transfers: Option<TransferOptions>,
+    /// Route colours
+    pub colors: ColorTable,
+    /// Distance in metres under which line string endpoints are joined
+    pub snap_tolerance: f64,
+    /// Distance in metres under which line string fragments are stitched
+    /// together, bridging the gap between them
+    pub stitch_tolerance: f64,
+    /// Shape simplification and stop splicing
+    pub shapes: ShapeOptions,
+    /// Route type of every mode
+    pub route_types: RouteTypes,
+    /// Route long name and description templates
+    pub route_names: RouteNameTemplates,
+    /// File the route sequence stop patterns of route sections without
+    /// timetable are written to when set
+    pub stop_patterns: Option<String>,
+    /// Modes of the lines to convert, every mode when empty
+    pub modes: Vec<Mode>,
+    /// Validity of the services of calendar.txt
+    pub calendar: CalendarWindow,
+    pub trip_ids: TripIdScheme,
+    pub gtfs_dir: String,
+    pub geojson_dir: String,
+    pub reports_dir: String,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            frequencies: None,
+            transfers: None,
+            colors: ColorTable::default(),
+            snap_tolerance: DEFAULT_SNAP_TOLERANCE,
+            stitch_tolerance: DEFAULT_STITCH_TOLERANCE,
+            shapes: ShapeOptions::default(),
+            route_types: RouteTypes::default(),
+            route_names: RouteNameTemplates::default(),
+            stop_patterns: None,
+            modes: vec![],
+            calendar: CalendarWindow::default(),
+            trip_ids: TripIdScheme::Hash,
+            gtfs_dir: String::from("./gtfs"),
+            geojson_dir: String::from("./geojson"),
+            reports_dir: String::from("./reports"),
+        }
+    }
+}
+
+pub struct Route<'a> {
     line: &'a Line,
     inbound_graph: RouteGraph,
     outbound_graph: RouteGraph,
+    /// Outcome of decoding the inbound and outbound line strings
+    pub diagnostics: LineStringDiagnostics,
 }
 
 impl<'a> Route<'a> {
-    fn new(line: &'a Line) -> Route {
-        let inbound_paths = match line.inbound_sequence.as_ref() {
-            Some(ref seq) => linestrings_to_paths(&seq.line_strings),
-            None => vec![],
-        };
-        let outbound_paths = match line.outbound_sequence.as_ref() {
-            Some(ref seq) => linestrings_to_paths(&seq.line_strings),
-            None => vec![],
-        };
-        let mut inbound_graph = RouteGraph::new();
-        let mut outbound_graph = RouteGraph::new();
+    pub fn new(line: &'a Line, options: &Options) -> Route<'a> {
+        let (inbound_paths, mut diagnostics) = Route::paths(line.inbound_sequence.as_ref(), options);
+        let (outbound_paths, outbound_diagnostics) = Route::paths(line.outbound_sequence.as_ref(), options);
+        let mut inbound_graph = RouteGraph::with_tolerance(options.snap_tolerance);
+        let mut outbound_graph = RouteGraph::with_tolerance(options.snap_tolerance);
 
         inbound_graph.add_paths(&inbound_paths);
         outbound_graph.add_paths(&outbound_paths);
+        diagnostics.merge(outbound_diagnostics);
 
         Route {
             line: line,
             inbound_graph: inbound_graph,
             outbound_graph: outbound_graph,
+            diagnostics: diagnostics,
         }
     }
-}
 
-fn route_type(line: &Line) -> &'static str {
-    match &line.mode_name[..] {
-        "dlr" | "tram" => "0",
-        "tube" | "overground" => "1",
-        "national-rail" | "tflrail" => "2",
-        "bus" => "3",
-        "river-tour" | "river-bus" => "4",
-        "cable-car" => "5",
-        _ => {
-            println!("Missing line mode_name match: {}", line.mode_name);
-            ""
-        },
+    /// Decoded and stitched paths of a route sequence
+    fn paths(sequence: Option<&Sequence>, options: &Options) -> (Vec<Vec<Point>>, LineStringDiagnostics) {
+        match sequence {
+            Some(seq) => {
+                let (paths, mut diagnostics) = linestrings_to_paths(&seq.line_strings);
+                let (paths, gaps_closed) = stitch_paths(paths, options.stitch_tolerance);
+                diagnostics.gaps_closed = gaps_closed;
+                (paths, diagnostics)
+            },
+            None => (vec![], LineStringDiagnostics::default()),
+        }
     }
 }
 
-fn write_agency(gtfs_path: &str) {
+fn write_agency(gtfs_path: &str, agencies: &[&Agency]) {
     let fname = format!("{}/{}", gtfs_path, "/agency.txt");
     let fpath = Path::new(&fname);
     let mut wtr = csv::Writer::from_file(fpath).unwrap();
-    let records = vec![
-        ("agency_id","agency_name","agency_url","agency_timezone"),
-        ("tfl","Transport For London","https://tfl.gov.uk","Europe/London")
-    ];
-    for record in records {
-        wtr.encode(record).unwrap();
+    wtr.encode(("agency_id", "agency_name", "agency_url", "agency_timezone", "agency_phone")).unwrap();
+    for agency in agencies {
+        wtr.encode((agency.id, agency.name, agency.url, "Europe/London", agency.phone)).unwrap();
     }
 }
 
-fn write_routes(gtfs_path: &str, routes: &Vec<Route>) {
-    let fname = format!("{}/{}", gtfs_path, "/routes.txt");
-    let fpath = Path::new(&fname);
-    let mut wtr = csv::Writer::from_file(fpath).unwrap();
-    wtr.encode(("route_id", "agency_id", "route_color", "route_short_name", "route_long_name", "route_type")).unwrap();
+/// Write the routes, returning the ids of the lines without colour
+fn write_routes(wtr: &mut csv::Writer<File>, routes: &[Route], options: &Options) -> Vec<String> {
+    let mut missing_colors = Vec::new();
     for route in routes {
         let line = &route.line;
-        let line_color = line.color();
-        wtr.encode((&line.id, "tfl", &line_color, &line.name, "", route_type(&line))).unwrap();
+        let agency = line_agency(line);
+        let (color, text_color) = match options.colors.line_color(line) {
+            Some(route_color) => (&route_color.color[..], &route_color.text_color[..]),
+            None => {
+                missing_colors.push(line.id.clone());
+                ("", "")
+            },
+        };
+        let long_name = options.route_names.long_name(line, agency);
+        let desc = options.route_names.desc(line, agency);
+        wtr.encode((&line.id, agency.id, color, text_color, &line.name, &long_name, &desc, options.route_types.route_type(&line.mode))).unwrap();
     }
+    missing_colors
 }
 
-fn write_stops(gtfs_path: &str, routes: &[Route]) -> HashMap<String, (f64, f64)> {
-    let fname = format!("{}/{}", gtfs_path, "/stops.txt");
-    let fpath = Path::new(&fname);
-    let mut wtr = csv::Writer::from_file(fpath).unwrap();
-    let mut written_stops = HashMap::<String, (f64, f64)>::new();
-    wtr.encode(("stop_id", "stop_name", "stop_lat", "stop_lon")).unwrap();
+/// Stops of a line and of its timetables as (id, name, lat, lon), child stop
+/// points being located at their parent. May contain duplicates.
+pub fn line_stops(line: &Line) -> Vec<(String, String, f64, f64)> {
+    let mut stops = Vec::new();
 
-    for route in routes {
-        let stops = route.line.stops.as_ref().unwrap();
+    if let Some(ref line_stops) = line.stops {
+        for stop in line_stops {
+            stops.push((stop.naptan_id.clone(), stop.common_name.clone(), stop.lat, stop.lon));
 
-        for stop in stops {
-            if !written_stops.contains_key(&stop.naptan_id) {
-                wtr.encode((stop.naptan_id.clone(), stop.common_name.clone(), stop.lat, stop.lon)).unwrap();
-                written_stops.insert(stop.naptan_id.clone(), (stop.lat, stop.lon));
+            for child in &stop.children {
+                stops.push((child.naptan_id.clone(), child.common_name.clone(), stop.lat, stop.lon));
+            }
+        }
+    }
 
-                for child in &stop.children {
-                    if !written_stops.contains_key(&child.naptan_id) {
-                        wtr.encode((child.naptan_id.clone(), child.common_name.clone(), stop.lat, stop.lon)).unwrap();
-                        written_stops.insert(child.naptan_id.clone(), (stop.lat, stop.lon));
-                    }
-                }
+    for section in &line.route_sections {
+        if let Some(ref timetable) = section.timetable {
+            for station in timetable.stations.iter().chain(timetable.stops.iter()) {
+                stops.push((station.id.clone(), station.name.clone(), station.lat, station.lon));
             }
         }
+    }
 
-        for section in &route.line.route_sections {
-            if let Some(ref timetable) = section.timetable {
-                for station in &timetable.stations {
-                    if !written_stops.contains_key(&station.id) {
-                        wtr.encode((station.id.clone(), station.name.clone(), station.lat, station.lon)).unwrap();
-                        written_stops.insert(station.id.clone(), (station.lat, station.lon));
-                    }
-                }
+    stops
+}
 
-                for stop in &timetable.stops {
-                    if !written_stops.contains_key(&stop.id) {
-                        wtr.encode((stop.id.clone(), stop.name.clone(), stop.lat, stop.lon)).unwrap();
-                        written_stops.insert(stop.id.clone(), (stop.lat, stop.lon));
-                    }
-                }
+/// Write the stops not written yet, recording their location
+fn write_stops(wtr: &mut csv::Writer<File>, routes: &[Route], written_stops: &mut HashMap<String, (f64, f64)>) {
+    for route in routes {
+        for (id, name, lat, lon) in line_stops(route.line) {
+            if !written_stops.contains_key(&id) {
+                wtr.encode((&id, &name, lat, lon)).unwrap();
+                written_stops.insert(id, (lat, lon));
             }
         }
     }
+}
 
-    written_stops
+/// Service ids of the calendar with the days they run on, Monday first
+const SERVICES: &'static [(&'static str, [&'static str; 7])] = &[
+    ("School Monday", ["1", "0", "0", "0", "0", "0", "0"]),
+    ("Sunday Night/Monday Morning", ["1", "0", "0", "0", "0", "0", "1"]),
+    ("School Monday, Tuesday, Thursday & Friday", ["1", "1", "0", "1", "1", "0", "0"]),
+    ("Tuesday", ["0", "1", "0", "0", "0", "0", "0"]),
+    ("Monday - Thursday", ["1", "1", "1", "1", "0", "0", "0"]),
+    ("Saturday", ["0", "0", "0", "0", "0", "1", "0"]),
+    ("Saturday and Sunday", ["0", "0", "0", "0", "0", "1", "1"]),
+    ("Sunday", ["0", "0", "0", "0", "0", "0", "1"]),
+    ("School Tuesday", ["0", "1", "0", "0", "0", "0", "0"]),
+    ("Saturday Night/Sunday Morning", ["0", "0", "0", "0", "0", "1", "1"]),
+    ("Mo-Fr Night/Tu-Sat Morning", ["1", "1", "1", "1", "1", "1", "0"]),
+    ("Monday to Thursday", ["1", "1", "1", "1", "0", "0", "0"]),
+    ("Mo-Th Nights/Tu-Fr Morning", ["1", "1", "1", "1", "1", "0", "0"]),
+    ("Saturday (also Good Friday)", ["0", "0", "0", "0", "0", "1", "0"]),
+    ("Mon-Th Schooldays", ["1", "1", "1", "1", "0", "0", "0"]),
+    ("Saturdays and Public Holidays", ["0", "0", "0", "0", "0", "1", "0"]),
+    ("Friday Night/Saturday Morning", ["0", "0", "0", "0", "1", "1", "0"]),
+    ("Friday", ["0", "0", "0", "0", "1", "0", "0"]),
+    ("Thursdays", ["0", "0", "0", "1", "0", "0", "0"]),
+    ("Sunday night/Monday morning - Thursday night/Friday morning", ["1", "1", "1", "1", "1", "0", "1"]),
+    ("School Thursday", ["0", "0", "0", "1", "0", "0", "0"]),
+    ("School Friday", ["0", "0", "0", "0", "1", "0", "0"]),
+    ("Daily", ["1", "1", "1", "1", "1", "1", "1"]),
+    ("Tuesday, Wednesday & Thursday", ["0", "1", "1", "1", "0", "0", "0"]),
+    ("Mon-Fri Schooldays", ["1", "1", "1", "1", "1", "0", "0"]),
+    ("Wednesday", ["0", "0", "1", "0", "0", "0", "0"]),
+    ("Monday, Tuesday and Thursday", ["1", "1", "0", "1", "0", "0", "0"]),
+    ("Wednesdays", ["0", "0", "1", "0", "0", "0", "0"]),
+    ("Monday to Friday", ["1", "1", "1", "1", "1", "0", "0"]),
+    ("Monday", ["1", "0", "0", "0", "0", "0", "0"]),
+    ("Sunday and other Public Holidays", ["0", "0", "0", "0", "0", "0", "1"]),
+    ("School Wednesday", ["0", "0", "1", "0", "0", "0", "0"]),
+    ("Monday - Friday", ["1", "1", "1", "1", "1", "0", "0"]),
+];
+
+/// Whether a schedule name is a service id of the calendar
+pub fn is_known_service(name: &str) -> bool {
+    SERVICES.iter().any(|&(service_id, _)| service_id == name)
 }
 
-fn write_calendar(gtfs_path: &str) {
+fn write_calendar(gtfs_path: &str, calendar: &CalendarWindow) {
     let fname = format!("{}/{}", gtfs_path, "/calendar.txt");
     let fpath = Path::new(&fname);
     let mut wtr = csv::Writer::from_file(fpath).unwrap();
-    let start_date = "20151031";
-    let end_date = "20161031";
-    let records = vec![
-        ("service_id", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday", "start_date", "end_date"),
-        ("School Monday", "1", "0", "0", "0", "0", "0", "0", &start_date, &end_date),
-        ("Sunday Night/Monday Morning", "1", "0", "0", "0", "0", "0", "1", &start_date, &end_date),
-        ("School Monday, Tuesday, Thursday & Friday", "1", "1", "0", "1", "1", "0", "0", &start_date, &end_date),
-        ("Tuesday", "0", "1", "0", "0", "0", "0", "0", &start_date, &end_date),
-        ("Monday - Thursday", "1", "1", "1", "1", "0", "0", "0", &start_date, &end_date),
-        ("Saturday", "0", "0", "0", "0", "0", "1", "0", &start_date, &end_date),
-        ("Saturday and Sunday", "0", "0", "0", "0", "0", "1","1", &start_date, &end_date),
-        ("Sunday", "0", "0", "0", "0", "0", "0", "1", &start_date, &end_date),
-        ("School Tuesday", "0", "1", "0", "0", "0", "0", "0", &start_date, &end_date),
-        ("Saturday Night/Sunday Morning", "0", "0", "0", "0", "0", "1", "1", &start_date, &end_date),
-        ("Mo-Fr Night/Tu-Sat Morning", "1", "1", "1", "1","1", "1", "0", &start_date, &end_date),
-        ("Monday to Thursday", "1", "1", "1", "1", "0", "0", "0", &start_date, &end_date),
-        ("Mo-Th Nights/Tu-Fr Morning", "1", "1", "1", "1", "1", "0", "0", &start_date, &end_date),
-        ("Saturday (also Good Friday)", "0", "0", "0", "0", "0", "1", "0", &start_date, &end_date),
-        ("Mon-Th Schooldays", "1", "1", "1", "1", "0", "0", "0", &start_date, &end_date),
-        ("Saturdays and Public Holidays", "0", "0", "0", "0", "0", "1", "0", &start_date, &end_date),
-        ("Friday Night/Saturday Morning", "0", "0", "0", "0", "1", "1", "0", &start_date, &end_date),
-        ("Friday", "0", "0", "0", "0", "1", "0", "0", &start_date, &end_date),
-        ("Thursdays", "0", "0", "0", "1", "0", "0", "0", &start_date, &end_date),
-        ("Sunday night/Monday morning - Thursday night/Friday morning", "1", "1", "1", "1", "1", "0", "1", &start_date, &end_date),
-        ("School Thursday", "0", "0", "0", "1", "0", "0", "0", &start_date, &end_date),
-        ("School Friday", "0", "0", "0", "0", "1", "0", "0", &start_date, &end_date),
-        ("Daily", "1", "1", "1", "1", "1", "1", "1", &start_date, &end_date),
-        ("Tuesday, Wednesday & Thursday", "0", "1", "1", "1", "0", "0", "0", &start_date, &end_date),
-        ("Mon-Fri Schooldays", "1", "1", "1", "1", "1", "0", "0", &start_date, &end_date),
-        ("Wednesday", "0", "0", "1", "0", "0", "0", "0", &start_date, &end_date),
-        ("Monday, Tuesday and Thursday", "1", "1", "0", "1", "0", "0", "0", &start_date, &end_date),
-        ("Wednesdays", "0", "0", "1", "0", "0", "0", "0", &start_date, &end_date),
-        ("Monday to Friday", "1", "1", "1", "1", "1", "0", "0", &start_date, &end_date),
-        ("Monday", "1", "0", "0", "0", "0", "0", "0", &start_date, &end_date),
-        ("Sunday and other Public Holidays", "0", "0", "0", "0", "0", "0", "1", &start_date, &end_date),
-        ("School Wednesday", "0", "0", "1", "0", "0", "0", "0", &start_date, &end_date),
-        ("Monday - Friday", "1", "1", "1", "1", "1", "0", "0", &start_date, &end_date),
-    ];
-
-    for record in records {
-        wtr.encode(record).unwrap();
-    }
-}
-
-fn trip_id(line: &Line, section: &RouteSection, schedule: &Schedule, journey: &KnownJourney) -> String {
+    let start_date = &calendar.start_date;
+    let end_date = &calendar.end_date;
+    wtr.encode(("service_id", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday", "start_date", "end_date")).unwrap();
+
+    for &(service_id, days) in SERVICES {
+        wtr.encode((service_id, days[0], days[1], days[2], days[3], days[4], days[5], days[6], start_date, end_date)).unwrap();
+    }
+}
+
+/// Separator of the hashed parts so moving characters between parts changes
+/// the hash
+const HASH_SEPARATOR: &'static str = "\u{1f}";
+
+fn scheme_id(scheme: &TripIdScheme, parts: &[&str]) -> String {
+    match *scheme {
+        TripIdScheme::Hash => {
+            let mut hasher = Md5::new();
+            hasher.input_str(&parts.join(HASH_SEPARATOR));
+            hasher.result_str()
+        },
+        TripIdScheme::Readable => parts.join("-"),
+    }
+}
+
+fn trip_id(scheme: &TripIdScheme, line: &Line, section: &RouteSection, schedule: &Schedule, journey: &KnownJourney) -> String {
     let tfmt = time_offset_fmt(journey, 0.0);
-    let input = line.id.to_string() + &section.originator + &section.destination + &schedule.name + &tfmt;
-    let mut hasher = Md5::new();
+    scheme_id(scheme, &[&line.id, &section.originator, &section.destination, &schedule.name, &tfmt])
+}
 
-    hasher.input_str(&input);
+/// Trip ids given to the journeys of a route. Journeys are visited in the
+/// same order when writing trips, stop times and frequencies, so the nth
+/// journey building an id gets the nth trip id recorded for it. Ids are only
+/// precise to the minute and a journey building an id already in the feed
+/// gets a numbered suffix instead.
+#[derive(Debug, Default)]
+struct RouteTrips {
+    ids: HashMap<String, Vec<String>>,
+    count: usize,
+    collisions: usize,
+}
+
+impl RouteTrips {
+    /// Give a trip id unique in the feed to a journey building the given id
+    fn add(&mut self, id: String, feed_trips: &mut HashSet<String>) -> String {
+        let mut trip_id = id.clone();
+        let mut n = 1;
+        while !feed_trips.insert(trip_id.clone()) {
+            n += 1;
+            trip_id = format!("{}-{}", id, n);
+        }
+        if n > 1 {
+            self.collisions += 1;
+        }
+        self.count += 1;
+        self.ids.entry(id).or_insert_with(Vec::new).push(trip_id.clone());
+        trip_id
+    }
 
-    hasher.result_str()
+    fn lookup(&self) -> TripIdLookup {
+        TripIdLookup {
+            trips: self,
+            seen: HashMap::new(),
+        }
+    }
+}
+
+/// Trip ids of a route looked up in the order they were given
+struct TripIdLookup<'a> {
+    trips: &'a RouteTrips,
+    seen: HashMap<String, usize>,
+}
+
+impl<'a> TripIdLookup<'a> {
+    /// Trip id of the next journey building the given id
+    fn next(&mut self, id: String) -> Option<&'a str> {
+        let trips = self.trips;
+        let n = self.seen.entry(id.clone()).or_insert(0);
+        *n += 1;
+        trips.ids.get(&id).and_then(|ids| ids.get(*n - 1)).map(|x| &x[..])
+    }
 }
 
-fn write_route_section_trips(wtr: &mut csv::Writer<File>, shape_id: &str, line: &Line, section: &RouteSection) {
-    let mut written_trips : HashSet<String> = HashSet::new();
-    let direction = match &section.direction[..] {
-        "inbound" => "1".to_owned(),
-        "outbound" => "0".to_owned(),
-        _ => "".to_owned(),
+/// Journeys of a schedule which get their own trip. With frequency compaction
+/// enabled only the template journey of each run is kept.
+fn schedule_journeys<'a>(schedule: &'a Schedule, options: &Options) -> Vec<&'a KnownJourney> {
+    match options.frequencies {
+        Some(ref frequency_options) => {
+            journey_runs(schedule, frequency_options).iter()
+                                                     .map(|run| run.template())
+                                                     .collect()
+        },
+        None => schedule.known_journeys.iter().collect(),
+    }
+}
+
+/// Write the trips of a route section, adding their ids to the feed and
+/// route trip ids
+fn write_route_section_trips(wtr: &mut csv::Writer<File>, shape_id: &str, line: &Line, section: &RouteSection, options: &Options, feed_trips: &mut HashSet<String>, route_trips: &mut RouteTrips) {
+    let direction = match section.direction {
+        Direction::Inbound => "1",
+        Direction::Outbound => "0",
+        Direction::Unknown(_) => "",
     };
 
     if let Some(timetable) = section.timetable.as_ref() {
@@ -197,13 +384,9 @@ fn write_route_section_trips(wtr: &mut csv::Writer<File>, shape_id: &str, line:
 
         if let Some(ref x) = first {
             for schedule in &x.schedules {
-                for journey in &schedule.known_journeys {
-                    let id = trip_id(line, section, schedule, journey);
-
-                    if !written_trips.contains(&id) {
-                        written_trips.insert(id.clone());
-                        wtr.encode((&line.id, &schedule.name, &id, &direction, &shape_id)).unwrap();
-                    }
+                for journey in schedule_journeys(schedule, options) {
+                    let id = route_trips.add(trip_id(&options.trip_ids, line, section, schedule, journey), feed_trips);
+                    wtr.encode((&line.id, &schedule.name, &id, &direction, &shape_id)).unwrap();
                 }
             }
         }
@@ -214,23 +397,25 @@ pub fn route_section_id(line: &Line, section: &RouteSection) -> String {
     line.id.clone() + " " + &section.originator + " to " + &section.destination
 }
 
-fn write_trips(gtfs_path: &str, routes: &[Route]) {
-    let fname = format!("{}/{}", gtfs_path, "/trips.txt");
-    let fpath = Path::new(&fname);
-    let mut wtr = csv::Writer::from_file(fpath).unwrap();
-    wtr.encode(("route_id", "service_id", "trip_id", "direction", "shape_id")).unwrap();
+/// Write the trips of every route with ids unique across the feed. Returns
+/// the trip ids of each route, used for its stop times and frequencies.
+fn write_trips(wtr: &mut csv::Writer<File>, routes: &[Route], options: &Options, feed_trips: &mut HashSet<String>) -> Vec<RouteTrips> {
+    let mut trips = Vec::with_capacity(routes.len());
     for route in routes {
         let mut written_route_sections = HashSet::<String>::new();
+        let mut route_trips = RouteTrips::default();
         let route_sections = &route.line.route_sections;
         for route_section in route_sections {
             let id = route_section_id(route.line, route_section);
 
             if !written_route_sections.contains(&id) {
-                write_route_section_trips(&mut wtr, &id, route.line, route_section);
+                write_route_section_trips(wtr, &id, route.line, route_section, options, feed_trips, &mut route_trips);
                 written_route_sections.insert(id);
             }
         }
+        trips.push(route_trips);
     }
+    trips
 }
 
 fn time_offset_fmt(journey: &KnownJourney, offset: f64) -> String {
@@ -243,128 +428,508 @@ fn time_offset_fmt(journey: &KnownJourney, offset: f64) -> String {
     format!("{:02}:{:02}:00", hour, minute)
 }
 
-fn write_journey_stop_times(wtr: &mut csv::Writer<File>, line: &Line, section: &RouteSection, schedule: &Schedule, journey: &KnownJourney, interval: &StationInterval) {
+fn write_journey_stop_times(wtr: &mut csv::Writer<Vec<u8>>, trip_id: &str, section: &RouteSection, journey: &KnownJourney, interval: &StationInterval, distances: &[String]) -> usize {
     let mut stop_seq = 1;
-    let trip_id = trip_id(line, section, schedule, journey);
     let dep_time = time_offset_fmt(journey, 0.0);
-    wtr.encode((&trip_id, &section.originator, stop_seq, &dep_time, &dep_time)).unwrap();
+    let distance = |seq: usize| distances.get(seq - 1).map_or("", |x| &x[..]);
+    wtr.encode((trip_id, &section.originator, stop_seq, &dep_time, &dep_time, distance(stop_seq))).unwrap();
     for stop in &interval.intervals {
         stop_seq += 1;
         let dep_time = time_offset_fmt(journey, stop.time_to_arrival);
-        wtr.encode((&trip_id, &stop.stop_id, stop_seq, &dep_time, &dep_time)).unwrap();
+        wtr.encode((trip_id, &stop.stop_id, stop_seq, &dep_time, &dep_time, distance(stop_seq))).unwrap();
+    }
+    stop_seq
+}
+
+/// Distance along the shape of every stop of an interval, starting with the
+/// originator. Empty when the section has no shape or a stop is unknown.
+fn interval_distances(section: &RouteSection, interval: &StationInterval, shape: Option<&Vec<Point>>, stops: &HashMap<String, (f64, f64)>) -> Vec<String> {
+    let stop_ids = Some(&section.originator).into_iter().chain(interval.intervals.iter().map(|x| &x.stop_id));
+    stop_distances(stop_ids, shape, stops)
+}
+
+/// Distance along the shape of every given stop, in order. Empty when there
+/// is no shape or a stop is unknown.
+fn stop_distances<'b, I: Iterator<Item=&'b String>>(stop_ids: I, shape: Option<&Vec<Point>>, stops: &HashMap<String, (f64, f64)>) -> Vec<String> {
+    let path = match shape {
+        Some(path) => path,
+        None => return vec![],
+    };
+    let path_distances = path_distances(path);
+    let mut distances = Vec::new();
+    let mut segment = 0;
+    let mut last_distance = 0.0;
+
+    for stop_id in stop_ids {
+        match stops.get(stop_id) {
+            Some(&(lat, lon)) => {
+                match project_onto_polyline(path, &path_distances, &Point::new(lat, lon), segment) {
+                    Some(projection) => {
+                        segment = projection.segment;
+                        last_distance = projection.distance_along.max(last_distance);
+                        distances.push(format!("{:.1}", last_distance));
+                    },
+                    None => return vec![],
+                }
+            },
+            None => return vec![],
+        }
     }
+
+    distances
 }
 
 fn intervals(station_intervals: &[StationInterval]) -> HashMap<i64, &StationInterval> {
     station_intervals.iter().map(|x| (x.id, x)).collect()
 }
 
-fn write_route_section_stop_times(wtr: &mut csv::Writer<File>, line: &Line, section: &RouteSection) {
+/// Write the stop times of the trips of a route section, returning the number
+/// of rows
+fn write_route_section_stop_times(wtr: &mut csv::Writer<Vec<u8>>, line: &Line, section: &RouteSection, trip_ids: &mut TripIdLookup, options: &Options, shapes: &Shapes, stops: &HashMap<String, (f64, f64)>) -> usize {
+    let mut row_count = 0;
+
     if let Some(timetable) = section.timetable.as_ref() {
-        let mut written_trips : HashSet<String> = HashSet::new();
         let record: Option<&TimeTable> = timetable.first_timetable();
 
         if let Some(ref datum) = record {
             let intervals = intervals(&datum.station_intervals);
+            let shape = shapes.get(&route_section_id(line, section));
+            let distances: HashMap<i64, Vec<String>> = intervals.iter().map(|(id, interval)| {
+                (*id, interval_distances(section, interval, shape, stops))
+            }).collect();
 
             for schedule in &datum.schedules {
-                for journey in &schedule.known_journeys {
-                    let log_exception = || { println!("Error, Could not find interval for schedule!!!!"); };
+                for journey in schedule_journeys(schedule, options) {
+                    let log_exception = || { warn!("Could not find interval {} for schedule {} of {}", journey.interval_id, schedule.name, route_section_id(line, section)); };
+                    let id = trip_ids.next(trip_id(&options.trip_ids, line, section, schedule, journey));
 
                     intervals.get(&journey.interval_id)
                              .map_or_else(log_exception, |interval| {
-                                let id = trip_id(line, section, schedule, journey);
-
-                                if !written_trips.contains(&id) {
-                                    written_trips.insert(id.clone());
-                                    write_journey_stop_times(wtr, line, section, schedule, journey, interval);
+                                if let Some(id) = id {
+                                    row_count += write_journey_stop_times(wtr, id, section, journey, interval, &distances[&journey.interval_id]);
                                 }
                              });
                 }
             }
         }
     }
+
+    row_count
 }
 
-fn write_stop_times(gtfs_path: &str, routes: &[Route]) {
-    let fname = format!("{}/{}", gtfs_path, "/stop_times.txt");
-    let fpath = Path::new(&fname);
-    let mut wtr = csv::Writer::from_file(fpath).unwrap();
-    wtr.encode(("trip_id", "stop_id", "stop_sequence", "arrival_time", "departure_time")).unwrap();
-    for route in routes {
+/// Stop times rows of the given trips of a route and their number, rendered
+/// in memory so routes can be handled in parallel
+fn route_stop_times(route: &Route, trips: &RouteTrips, options: &Options, shapes: &Shapes, stops: &HashMap<String, (f64, f64)>) -> (Vec<u8>, usize) {
+    let mut wtr = csv::Writer::from_memory();
+    let mut row_count = 0;
+    let mut trip_ids = trips.lookup();
+    let mut written_route_sections = HashSet::<String>::new();
+    let route_sections = &route.line.route_sections;
+    for route_section in route_sections {
+        let id = route_section_id(route.line, route_section);
+
+        if !written_route_sections.contains(&id) {
+            row_count += write_route_section_stop_times(&mut wtr, route.line, route_section, &mut trip_ids, options, shapes, stops);
+            written_route_sections.insert(id);
+        }
+    }
+    (wtr.into_bytes(), row_count)
+}
+
+/// Write the stop times of every route, returning the number of rows of each
+fn write_stop_times(pool: &mut Pool, f: &mut File, routes: &[Route], trips: &[RouteTrips], options: &Options, shapes: &Shapes, stops: &HashMap<String, (f64, f64)>) -> Vec<usize> {
+    let items: Vec<(&Route, &RouteTrips)> = routes.iter().zip(trips).collect();
+    let chunks = parallel_map(pool, &items, |&(route, route_trips)| route_stop_times(route, route_trips, options, shapes, stops));
+    let mut row_counts = Vec::with_capacity(chunks.len());
+    for (chunk, row_count) in chunks {
+        f.write_all(&chunk).unwrap();
+        row_counts.push(row_count);
+    }
+    row_counts
+}
+
+fn write_route_section_frequencies(wtr: &mut csv::Writer<File>, line: &Line, section: &RouteSection, trip_ids: &mut TripIdLookup, scheme: &TripIdScheme, frequency_options: &FrequencyOptions) {
+    if let Some(timetable) = section.timetable.as_ref() {
+        let record: Option<&TimeTable> = timetable.first_timetable();
+
+        if let Some(ref datum) = record {
+            for schedule in &datum.schedules {
+                for run in journey_runs(schedule, frequency_options) {
+                    let id = trip_ids.next(trip_id(scheme, line, section, schedule, run.template()));
+                    if let (true, Some(id)) = (run.is_frequency(), id) {
+                        let exact_times = if frequency_options.exact_times && run.is_exact() { "1" } else { "0" };
+                        wtr.encode((id, seconds_fmt(run.start_time()), seconds_fmt(run.end_time()), run.headway, exact_times)).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn write_frequencies(wtr: &mut csv::Writer<File>, routes: &[Route], trips: &[RouteTrips], options: &Options) {
+    for (route, route_trips) in routes.iter().zip(trips) {
+        let mut trip_ids = route_trips.lookup();
         let mut written_route_sections = HashSet::<String>::new();
         let route_sections = &route.line.route_sections;
         for route_section in route_sections {
             let id = route_section_id(route.line, route_section);
 
             if !written_route_sections.contains(&id) {
-                write_route_section_stop_times(&mut wtr, route.line, route_section);
+                if let Some(ref frequency_options) = options.frequencies {
+                    write_route_section_frequencies(wtr, route.line, route_section, &mut trip_ids, &options.trip_ids, frequency_options);
+                }
                 written_route_sections.insert(id);
             }
         }
     }
 }
 
+fn write_transfers(gtfs_path: &str, lines: &[&Line], transfer_options: &TransferOptions) {
+    let fname = format!("{}/{}", gtfs_path, "/transfers.txt");
+    let fpath = Path::new(&fname);
+    let mut wtr = csv::Writer::from_file(fpath).unwrap();
+    wtr.encode(("from_stop_id", "to_stop_id", "transfer_type", "min_transfer_time")).unwrap();
+    for transfer in transfers(lines, transfer_options) {
+        wtr.encode((&transfer.from_stop_id, &transfer.to_stop_id, "2", transfer.min_transfer_time)).unwrap();
+    }
+}
+
 fn write_shape_path(wtr: &mut csv::Writer<File>, shape_id: &str, path: &[Point]) {
+    let distances = path_distances(path);
     for (seq, item) in path.iter().enumerate() {
-        wtr.encode((shape_id, item.lat(), item.lon(), seq)).unwrap();
+        wtr.encode((shape_id, item.lat(), item.lon(), seq, format!("{:.1}", distances[seq]))).unwrap();
     }
 }
 
-fn write_shape(wtr: &mut csv::Writer<File>, shape_id: &str, _route: &Route, section: &RouteSection, stops: &HashMap<String, (f64, f64)>, graph: &RouteGraph) {
+fn route_section_shape(shape_id: &str, section: &RouteSection, stops: &HashMap<String, (f64, f64)>, graph: &RouteGraph) -> Option<Vec<Point>> {
     if let Some(&(start_lat, start_lon)) = stops.get(&section.originator) {
         let start_pt = Point::new(start_lat, start_lon);
 
         if let Some(&(end_lat, end_lon)) = stops.get(&section.destination) {
             let end_pt = Point::new(end_lat, end_lon);
             match graph.path(start_pt, end_pt) {
-                Some(path) => write_shape_path(wtr, shape_id, &path),
+                Some(path) => return Some(path),
                 None => {
-                    println!("could not find shape for {}!!!", shape_id);
+                    warn!("Could not find shape for {}", shape_id);
                 },
             }
         }
     }
+
+    None
 }
 
-fn write_shapes(gtfs_path: &str, routes: &[Route], stops: &HashMap<String, (f64, f64)>) {
-    let fname = format!("{}/{}", gtfs_path, "/shapes.txt");
-    let fpath = Path::new(&fname);
-    let mut wtr = csv::Writer::from_file(fpath).unwrap();
-    wtr.encode(("shape_id", "shape_pt_lat", "shape_pt_lon", "shape_pt_sequence")).unwrap();
+/// Coordinates of the stops served by a route section in order, taken from
+/// its longest station interval or else from its route sequence stop pattern
+fn route_section_stops(line: &Line, section: &RouteSection, stops: &HashMap<String, (f64, f64)>) -> Vec<Point> {
+    let stop_ids = match section.timetable.as_ref().and_then(|timetable| timetable.first_timetable()) {
+        Some(datum) => {
+            let mut stop_ids = vec![section.originator.clone()];
+            if let Some(interval) = datum.station_intervals.iter().max_by_key(|x| x.intervals.len()) {
+                stop_ids.extend(interval.intervals.iter().map(|x| x.stop_id.clone()));
+            }
+            stop_ids
+        },
+        None => section_pattern(line, section).unwrap_or_else(|| vec![section.originator.clone()]),
+    };
+
+    stop_ids.iter().filter_map(|id| stops.get(id)).map(|&(lat, lon)| Point::new(lat, lon)).collect()
+}
+
+/// Shape path of every route section, keyed by shape id
+pub type Shapes = HashMap<String, Vec<Point>>;
+
+/// Shapes of the route sections of a single route
+pub fn line_shapes(route: &Route, stops: &HashMap<String, (f64, f64)>, shape_options: &ShapeOptions) -> Shapes {
+    let mut shapes = Shapes::new();
+    let route_sections = &route.line.route_sections;
+    for route_section in route_sections {
+        let shape_id = route_section_id(route.line, route_section);
+
+        if !shapes.contains_key(&shape_id) {
+            let graph = match route_section.direction {
+                Direction::Inbound => Some(&route.inbound_graph),
+                Direction::Outbound => Some(&route.outbound_graph),
+                Direction::Unknown(_) => None,
+            };
+
+            if let Some(graph) = graph {
+                if let Some(path) = route_section_shape(&shape_id, route_section, stops, graph) {
+                    let section_stops = route_section_stops(route.line, route_section, stops);
+                    shapes.insert(shape_id, process_shape(path, &section_stops, shape_options));
+                }
+            }
+        }
+    }
+    shapes
+}
+
+/// Shapes of every route computed on the pool, the first route listing a
+/// shape id providing its shape
+fn route_shapes(pool: &mut Pool, routes: &[Route], stops: &HashMap<String, (f64, f64)>, shape_options: &ShapeOptions) -> Shapes {
+    let mut shapes = Shapes::new();
+    for shapes_of_route in parallel_map(pool, routes, |route| line_shapes(route, stops, shape_options)) {
+        for (shape_id, path) in shapes_of_route {
+            shapes.entry(shape_id).or_insert(path);
+        }
+    }
+    shapes
+}
+
+/// Write the route sequence stop pattern of every route section without
+/// timetable, with the distance of each stop along the section shape. Sections
+/// with an unknown stop are left out.
+fn write_stop_patterns(wtr: &mut csv::Writer<File>, routes: &[Route], shapes: &Shapes, stops: &HashMap<String, (f64, f64)>) {
+    for route in routes {
+        let mut written_route_sections = HashSet::<String>::new();
+        for route_section in &route.line.route_sections {
+            let id = route_section_id(route.line, route_section);
+            if has_timetable(route_section) || !written_route_sections.insert(id.clone()) {
+                continue;
+            }
+
+            if let Some(stop_ids) = section_pattern(route.line, route_section) {
+                if stop_ids.iter().all(|stop_id| stops.contains_key(stop_id)) {
+                    let distances = stop_distances(stop_ids.iter(), shapes.get(&id), stops);
+                    for (i, stop_id) in stop_ids.iter().enumerate() {
+                        let distance = distances.get(i).map_or("", |x| &x[..]);
+                        wtr.encode((&id, &route.line.id, i + 1, stop_id, distance)).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn write_shapes(wtr: &mut csv::Writer<File>, routes: &[Route], shapes: &Shapes, written_shapes: &mut HashSet<String>) {
     for route in routes {
-        let mut written_shapes = HashSet::<String>::new();
         let route_sections = &route.line.route_sections;
         for route_section in route_sections {
             let shape_id = route_section_id(route.line, route_section);
 
             if !written_shapes.contains(&shape_id) {
-                let graph = match &route_section.direction[..] {
-                    "inbound" => Some(&route.inbound_graph),
-                    "outbound" => Some(&route.outbound_graph),
-                    _ => None,
-                };
-
-                if let Some(graph) = graph {
-                    write_shape(&mut wtr, &shape_id, route, route_section, stops, graph);
-                    written_shapes.insert(shape_id);
+                if let Some(path) = shapes.get(&shape_id) {
+                    write_shape_path(wtr, &shape_id, path);
                 }
+                written_shapes.insert(shape_id);
             }
         }
     }
 }
 
-pub fn write_gtfs(lines: &[Line]) {
-    let routes = lines.iter().map(|line| Route::new(line)).collect();
-    let gtfs_path : &Path = Path::new("./gtfs");
-    let gtfs_path_str = gtfs_path.to_str().unwrap();
-    let _ = fs::create_dir(gtfs_path_str);
-    write_agency(gtfs_path_str);
-    write_routes(gtfs_path_str, &routes);
-    let all_stops = write_stops(gtfs_path_str, &routes);
-    write_calendar(gtfs_path_str);
-    write_trips(gtfs_path_str, &routes);
-    write_stop_times(gtfs_path_str, &routes);
-    write_shapes(gtfs_path_str, &routes, &all_stops);
+fn feed_writer(gtfs_path: &str, name: &str) -> csv::Writer<File> {
+    let fname = format!("{}/{}", gtfs_path, name);
+    csv::Writer::from_file(Path::new(&fname)).unwrap()
+}
+
+/// Line reduced to what transfers need, so whole lines need not be kept
+fn transfer_line(line: &Line) -> Line {
+    Line {
+        id: line.id.clone(),
+        name: line.name.clone(),
+        mode: line.mode.clone(),
+        route_sections: vec![],
+        stops: line.stops.clone(),
+        inbound_sequence: None,
+        outbound_sequence: None,
+    }
 }
 
+/// Quality report of a converted route, given its trips and the number of
+/// stop times written for it
+fn line_report(route: Route, shapes: &Shapes, trips: &RouteTrips, stop_times: usize, options: &Options) -> LineReport {
+    let line = route.line;
+    let mut section_ids = HashSet::new();
+    let mut missing_timetables = 0;
+    let mut failed_shapes = 0;
+    let mut unknown_schedule_names = HashSet::new();
+
+    for section in &line.route_sections {
+        if !has_timetable(section) {
+            missing_timetables += 1;
+        }
+        if let Some(ref timetable) = section.timetable {
+            unknown_schedule_names.extend(timetable.schedule_names().into_iter().filter(|x| !is_known_service(x)));
+        }
+
+        let id = route_section_id(line, section);
+        if !shapes.contains_key(&id) && !section_ids.contains(&id) {
+            failed_shapes += 1;
+        }
+        section_ids.insert(id);
+    }
+
+    let mut unknown_schedule_names: Vec<String> = unknown_schedule_names.into_iter().collect();
+    unknown_schedule_names.sort();
+
+    LineReport {
+        line_id: line.id.clone(),
+        line_name: line.name.clone(),
+        mode: line.mode.as_str().to_owned(),
+        route_sections: line.route_sections.len(),
+        trips: trips.count,
+        trip_id_collisions: trips.collisions,
+        stop_times: stop_times,
+        missing_timetables: missing_timetables,
+        failed_shapes: failed_shapes,
+        unknown_color: options.colors.line_color(line).is_none(),
+        unknown_schedule_names: unknown_schedule_names,
+        decode_errors: route.diagnostics.errors.len(),
+        line_strings: route.diagnostics,
+    }
+}
+
+/// GTFS feed written into the GTFS directory one batch of lines at a time. Only the
+/// indexes spanning lines are kept between batches: written stops, shapes and
+/// trip ids, agencies and the stops of every line when transfers are enabled.
+pub struct FeedWriter<'a> {
+    options: &'a Options,
+    gtfs_path: String,
+    routes: csv::Writer<File>,
+    stops: csv::Writer<File>,
+    trips: csv::Writer<File>,
+    stop_times: File,
+    frequencies: Option<csv::Writer<File>>,
+    stop_patterns: Option<csv::Writer<File>>,
+    shapes: csv::Writer<File>,
+    agencies: Vec<&'static Agency>,
+    written_stops: HashMap<String, (f64, f64)>,
+    written_shapes: HashSet<String>,
+    written_trips: HashSet<String>,
+    transfer_lines: Vec<Line>,
+    missing_colors: Vec<String>,
+    reports: Vec<LineReport>,
+}
+
+impl<'a> FeedWriter<'a> {
+    /// Create the feed files
+    pub fn new(options: &'a Options) -> FeedWriter<'a> {
+        let gtfs_path = &options.gtfs_dir[..];
+        let _ = fs::create_dir_all(gtfs_path);
+
+        let mut routes = feed_writer(gtfs_path, "/routes.txt");
+        routes.encode(("route_id", "agency_id", "route_color", "route_text_color", "route_short_name", "route_long_name", "route_desc", "route_type")).unwrap();
+        let mut stops = feed_writer(gtfs_path, "/stops.txt");
+        stops.encode(("stop_id", "stop_name", "stop_lat", "stop_lon")).unwrap();
+        let mut trips = feed_writer(gtfs_path, "/trips.txt");
+        trips.encode(("route_id", "service_id", "trip_id", "direction", "shape_id")).unwrap();
+        let mut stop_times = File::create(Path::new(&format!("{}/{}", gtfs_path, "/stop_times.txt"))).unwrap();
+        let mut header = csv::Writer::from_memory();
+        header.encode(("trip_id", "stop_id", "stop_sequence", "arrival_time", "departure_time", "shape_dist_traveled")).unwrap();
+        stop_times.write_all(&header.into_bytes()).unwrap();
+        let frequencies = if options.frequencies.is_some() {
+            let mut wtr = feed_writer(gtfs_path, "/frequencies.txt");
+            wtr.encode(("trip_id", "start_time", "end_time", "headway_secs", "exact_times")).unwrap();
+            Some(wtr)
+        } else {
+            None
+        };
+        let stop_patterns = options.stop_patterns.as_ref().map(|path| {
+            let mut wtr = csv::Writer::from_file(Path::new(path)).unwrap();
+            wtr.encode(("pattern_id", "route_id", "stop_sequence", "stop_id", "shape_dist_traveled")).unwrap();
+            wtr
+        });
+        let mut shapes = feed_writer(gtfs_path, "/shapes.txt");
+        shapes.encode(("shape_id", "shape_pt_lat", "shape_pt_lon", "shape_pt_sequence", "shape_dist_traveled")).unwrap();
+        write_calendar(gtfs_path, &options.calendar);
+
+        FeedWriter {
+            options: options,
+            gtfs_path: gtfs_path.to_owned(),
+            routes: routes,
+            stops: stops,
+            trips: trips,
+            stop_times: stop_times,
+            frequencies: frequencies,
+            stop_patterns: stop_patterns,
+            shapes: shapes,
+            agencies: Vec::new(),
+            written_stops: HashMap::new(),
+            written_shapes: HashSet::new(),
+            written_trips: HashSet::new(),
+            transfer_lines: Vec::new(),
+            missing_colors: Vec::new(),
+            reports: Vec::new(),
+        }
+    }
+
+    /// Convert and write a batch of lines on the given pool, the batch can be
+    /// dropped afterwards. The output does not depend on the number of
+    /// threads of the pool.
+    pub fn write_lines(&mut self, pool: &mut Pool, lines: &[Line]) {
+        let options = self.options;
+        let routes: Vec<Route> = parallel_map(pool, lines, |line| Route::new(line, options));
+
+        for route in &routes {
+            let agency = line_agency(route.line);
+            if !self.agencies.iter().any(|x| x.id == agency.id) {
+                self.agencies.push(agency);
+            }
+            if options.transfers.is_some() {
+                self.transfer_lines.push(transfer_line(route.line));
+            }
+        }
+
+        let missing_colors = write_routes(&mut self.routes, &routes, options);
+        self.missing_colors.extend(missing_colors);
+        write_stops(&mut self.stops, &routes, &mut self.written_stops);
+        let shapes = route_shapes(pool, &routes, &self.written_stops, &options.shapes);
+        let trips = write_trips(&mut self.trips, &routes, options, &mut self.written_trips);
+        let stop_time_counts = write_stop_times(pool, &mut self.stop_times, &routes, &trips, options, &shapes, &self.written_stops);
+        if let Some(ref mut wtr) = self.frequencies {
+            write_frequencies(wtr, &routes, &trips, options);
+        }
+        write_shapes(&mut self.shapes, &routes, &shapes, &mut self.written_shapes);
+        if let Some(ref mut wtr) = self.stop_patterns {
+            write_stop_patterns(wtr, &routes, &shapes, &self.written_stops);
+        }
+
+        for ((route, route_trips), stop_times) in routes.into_iter().zip(trips).zip(stop_time_counts) {
+            self.reports.push(line_report(route, &shapes, &route_trips, stop_times, options));
+        }
+    }
+
+    /// Write the files depending on every line, returning the quality report
+    /// of every line
+    pub fn finish(self) -> Vec<LineReport> {
+        write_agency(&self.gtfs_path, &self.agencies);
+        if let Some(ref transfer_options) = self.options.transfers {
+            let lines: Vec<&Line> = self.transfer_lines.iter().collect();
+            write_transfers(&self.gtfs_path, &lines, transfer_options);
+        }
+
+        if !self.missing_colors.is_empty() {
+            warn!("Missing colors for {} lines: {}", self.missing_colors.len(), self.missing_colors.join(", "));
+        }
+
+        self.reports
+    }
+}
+
+#[test]
+fn scheme_id_test() {
+    assert!(scheme_id(&TripIdScheme::Hash, &["ab", "c"]) != scheme_id(&TripIdScheme::Hash, &["a", "bc"]));
+    assert_eq!(scheme_id(&TripIdScheme::Readable, &["a", "b"]), "a-b");
+}
+
+#[test]
+fn route_trips_test() {
+    let mut feed_trips = HashSet::new();
+    feed_trips.insert("b".to_owned());
+    let mut trips = RouteTrips::default();
+
+    assert_eq!(trips.add("a".to_owned(), &mut feed_trips), "a");
+    assert_eq!(trips.add("a".to_owned(), &mut feed_trips), "a-2");
+    assert_eq!(trips.add("b".to_owned(), &mut feed_trips), "b-2");
+    assert_eq!(trips.add("a".to_owned(), &mut feed_trips), "a-3");
+    assert_eq!((trips.count, trips.collisions), (4, 3));
+
+    // journeys are looked up in the order their ids were given
+    let mut trip_ids = trips.lookup();
+    assert_eq!(trip_ids.next("a".to_owned()), Some("a"));
+    assert_eq!(trip_ids.next("b".to_owned()), Some("b-2"));
+    assert_eq!(trip_ids.next("a".to_owned()), Some("a-2"));
+    assert_eq!(trip_ids.next("a".to_owned()), Some("a-3"));
+    assert_eq!(trip_ids.next("a".to_owned()), None);
+    assert_eq!(trip_ids.next("c".to_owned()), None);
+}
@@ -0,0 +1,14 @@
+use serde::Deserialize;
+use serde_json;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Read and parse a JSON file given on the command line or in a profile.
+/// Errors name the file and describe it as `kind`, e.g. "colour file".
+pub fn read_json_file<T: Deserialize>(path: &Path, kind: &str) -> Result<T, String> {
+    let mut body = String::new();
+    let mut f = try!(File::open(path).map_err(|err| format!("Could not open {}: {}", path.display(), err)));
+    try!(f.read_to_string(&mut body).map_err(|err| format!("Could not read {}: {}", path.display(), err)));
+    serde_json::from_str(&body).map_err(|err| format!("Invalid {} {}: {}", kind, path.display(), err))
+}
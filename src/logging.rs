@@ -0,0 +1,50 @@
+use env_logger::LogBuilder;
+use log::{LogLevelFilter, LogRecord, SetLoggerError};
+use serde_json;
+use std::collections::BTreeMap;
+use std::env;
+
+/// Log output settings
+#[derive(Clone, Debug, Default)]
+pub struct LogOptions {
+    /// Only log warnings and errors
+    pub quiet: bool,
+    /// Log one JSON object per line instead of plain text
+    pub json: bool,
+}
+
+impl LogOptions {
+    fn level(&self) -> LogLevelFilter {
+        if self.quiet { LogLevelFilter::Warn } else { LogLevelFilter::Info }
+    }
+
+    /// Progress bars would get in the way of quiet and JSON logs
+    pub fn show_progress(&self) -> bool {
+        !self.quiet && !self.json
+    }
+}
+
+/// Log record as a JSON line
+fn json_record(record: &LogRecord) -> String {
+    let mut entry = BTreeMap::new();
+    entry.insert("level", record.level().to_string());
+    entry.insert("target", record.target().to_owned());
+    entry.insert("message", record.args().to_string());
+    serde_json::to_string(&entry).unwrap()
+}
+
+/// Install the logger. Plain text and JSON logs both honour RUST_LOG on top
+/// of the level given by the options.
+pub fn init(options: &LogOptions) -> Result<(), SetLoggerError> {
+    let mut builder = LogBuilder::new();
+    if options.json {
+        builder.format(json_record);
+    } else {
+        builder.format(|record: &LogRecord| format!("{} [{}] {}", record.level(), record.target(), record.args()));
+    }
+    builder.filter(None, options.level());
+    if let Ok(spec) = env::var("RUST_LOG") {
+        builder.parse(&spec);
+    }
+    builder.init()
+}
@@ -6,10 +6,12 @@
 
 #[macro_use] extern crate clap;
 extern crate ansi_term;
+extern crate atty;
 extern crate crypto;
 extern crate csv;
 extern crate env_logger;
 extern crate hyper;
+#[macro_use] extern crate log;
 extern crate rand;
 extern crate scoped_threadpool;
 extern crate serde;
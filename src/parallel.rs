@@ -0,0 +1,28 @@
+use scoped_threadpool::Pool;
+
+/// Map every item on the pool, results being returned in the order of the
+/// items whatever order they complete in. Results may borrow from the items.
+pub fn parallel_map<'a, T, U, F>(pool: &mut Pool, items: &'a [T], f: F) -> Vec<U>
+    where T: Sync, U: Send, F: Fn(&'a T) -> U + Sync
+{
+    let mut results: Vec<Option<U>> = items.iter().map(|_| None).collect();
+
+    {
+        let f = &f;
+        pool.scoped(|scope| {
+            for (item, result) in items.iter().zip(results.iter_mut()) {
+                scope.execute(move || *result = Some(f(item)));
+            }
+        });
+    }
+
+    results.into_iter().map(|result| result.unwrap()).collect()
+}
+
+#[test]
+fn parallel_map_test() {
+    let mut pool = Pool::new(4);
+    let items: Vec<u64> = (0..100).collect();
+
+    assert_eq!(parallel_map(&mut pool, &items, |x| x * 2), (0..100).map(|x| x * 2).collect::<Vec<u64>>());
+}
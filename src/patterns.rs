@@ -0,0 +1,161 @@
+use tfl::line::{Direction, Line, RouteSection, Sequence, StationInterval, StopPointSequence};
+
+/// Whether a route section has a usable timetable
+pub fn has_timetable(section: &RouteSection) -> bool {
+    section.timetable.as_ref().map_or(false, |timetable| timetable.has_timetable())
+}
+
+/// Stop ids of every path through the branches of a route sequence, from a
+/// branch without predecessor to a branch without successor
+pub fn branch_patterns(sequence: &Sequence) -> Vec<Vec<String>> {
+    let mut patterns = Vec::new();
+    let mut roots: Vec<&StopPointSequence> = sequence.stop_point_sequences.iter()
+                                                                          .filter(|x| x.prev_branch_ids.is_empty())
+                                                                          .collect();
+
+    // a loop has no first branch, start from the first one listed
+    if roots.is_empty() {
+        roots.extend(sequence.stop_point_sequences.first());
+    }
+
+    for root in roots {
+        walk_branches(sequence, root, vec![], vec![], &mut patterns);
+    }
+
+    patterns
+}
+
+fn walk_branches(sequence: &Sequence, branch: &StopPointSequence, mut pattern: Vec<String>, mut visited: Vec<i64>, patterns: &mut Vec<Vec<String>>) {
+    for stop in &branch.stop_point {
+        // consecutive branches may both list the junction stop
+        if pattern.last() != Some(&stop.id) {
+            pattern.push(stop.id.clone());
+        }
+    }
+    visited.push(branch.branch_id);
+
+    let next: Vec<&StopPointSequence> = branch.next_branch_ids.iter()
+                                                              .filter(|&&id| !visited.contains(&id))
+                                                              .filter_map(|&id| sequence.branch(id))
+                                                              .collect();
+
+    if next.is_empty() {
+        patterns.push(pattern);
+    } else {
+        for next_branch in next {
+            walk_branches(sequence, next_branch, pattern.clone(), visited.clone(), patterns);
+        }
+    }
+}
+
+/// Ordered line routes followed by the branch paths of a route sequence
+pub fn sequence_patterns(sequence: &Sequence) -> Vec<Vec<String>> {
+    let mut patterns: Vec<Vec<String>> = sequence.ordered_line_routes.iter().map(|route| route.naptan_ids.clone()).collect();
+    patterns.extend(branch_patterns(sequence));
+    patterns
+}
+
+/// Route sequence of a line in the given direction
+fn direction_sequence<'a>(line: &'a Line, direction: &Direction) -> Option<&'a Sequence> {
+    match *direction {
+        Direction::Inbound => line.inbound_sequence.as_ref(),
+        Direction::Outbound => line.outbound_sequence.as_ref(),
+        Direction::Unknown(_) => None,
+    }
+}
+
+/// Index of the first pattern serving all the given stops in order
+pub fn matching_pattern(patterns: &[Vec<String>], stop_ids: &[&String]) -> Option<usize> {
+    patterns.iter().position(|pattern| {
+        let mut ids = pattern.iter();
+        stop_ids.iter().all(|stop_id| ids.any(|id| id == *stop_id))
+    })
+}
+
+/// Index of the pattern of a line serving a timetable station interval,
+/// starting at the route section originator
+pub fn interval_pattern(line: &Line, section: &RouteSection, interval: &StationInterval) -> Option<usize> {
+    let stop_ids: Vec<&String> = Some(&section.originator).into_iter()
+                                                           .chain(interval.intervals.iter().map(|x| &x.stop_id))
+                                                           .collect();
+
+    direction_sequence(line, &section.direction).and_then(|seq| matching_pattern(&sequence_patterns(seq), &stop_ids))
+}
+
+/// Stops from originator to destination of the first pattern serving both
+/// in that order
+pub fn stop_pattern(patterns: &[Vec<String>], originator: &str, destination: &str) -> Option<Vec<String>> {
+    for ids in patterns {
+        if let Some(start) = ids.iter().position(|id| id == originator) {
+            if let Some(end) = ids[start + 1 ..].iter().position(|id| id == destination) {
+                return Some(ids[start .. start + end + 2].to_vec());
+            }
+        }
+    }
+
+    None
+}
+
+/// Stop pattern of a route section, taken from the route sequence of its
+/// direction
+pub fn section_pattern(line: &Line, section: &RouteSection) -> Option<Vec<String>> {
+    direction_sequence(line, &section.direction).and_then(|seq| {
+        stop_pattern(&sequence_patterns(seq), &section.originator, &section.destination)
+    })
+}
+
+#[cfg(test)]
+fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn stop_pattern_test() {
+    let routes = vec![ids(&["a", "b", "c"]), ids(&["a", "b", "d", "e"])];
+
+    assert_eq!(stop_pattern(&routes, "b", "e"), Some(vec!["b".to_owned(), "d".to_owned(), "e".to_owned()]));
+    assert_eq!(stop_pattern(&routes, "a", "c"), Some(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]));
+    assert_eq!(stop_pattern(&routes, "c", "a"), None);
+}
+
+#[test]
+fn branch_patterns_test() {
+    use tfl::line::MatchedStop;
+
+    let branch = |branch_id, stops: &[&str], prev: Vec<i64>, next: Vec<i64>| StopPointSequence {
+        direction: Direction::Outbound,
+        branch_id: branch_id,
+        next_branch_ids: next,
+        prev_branch_ids: prev,
+        stop_point: stops.iter().map(|id| MatchedStop {
+            id: id.to_string(),
+            name: id.to_string(),
+            lat: 51.5,
+            lon: -0.1,
+            station_id: None,
+            top_most_parent_id: None,
+            stop_letter: None,
+            towards: None,
+            modes: vec![],
+        }).collect(),
+        service_type: None,
+    };
+    let sequence = Sequence {
+        line_strings: vec![],
+        stations: vec![],
+        stop_point_sequences: vec![
+            branch(0, &["a", "b"], vec![], vec![1, 2]),
+            branch(1, &["b", "c"], vec![0], vec![]),
+            branch(2, &["d", "e"], vec![0], vec![]),
+        ],
+        ordered_line_routes: vec![],
+    };
+    let patterns = branch_patterns(&sequence);
+
+    assert_eq!(patterns, vec![ids(&["a", "b", "c"]), ids(&["a", "b", "d", "e"])]);
+
+    let (a, c, d) = ("a".to_owned(), "c".to_owned(), "d".to_owned());
+    assert_eq!(matching_pattern(&patterns, &[&a, &d]), Some(1));
+    assert_eq!(matching_pattern(&patterns, &[&a, &c]), Some(0));
+    assert_eq!(matching_pattern(&patterns, &[&c, &a]), None);
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use colors::ColorEntry;
+use gtfs::{CalendarWindow, TripIdScheme};
+use json_file::read_json_file;
+use tfl::line::MODE_NAMES;
+
+/// TfL API credentials
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    pub app_id: String,
+    pub app_key: String,
+}
+
+/// Cache and output directories, the defaults apply to those left out
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dirs {
+    pub cache: Option<String>,
+    pub gtfs: Option<String>,
+    pub geojson: Option<String>,
+    pub reports: Option<String>,
+}
+
+/// Route type scheme and route types of modes, keyed by TfL mode name
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteTypeMapping {
+    pub extended: Option<bool>,
+    pub overrides: Option<HashMap<String, u16>>,
+}
+
+/// Conversion profile read from a JSON file. Every setting is optional and
+/// command line flags take precedence over the profile.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub credentials: Option<Credentials>,
+    pub dirs: Option<Dirs>,
+    /// Modes of the lines to convert
+    pub modes: Option<Vec<String>>,
+    pub calendar: Option<CalendarWindow>,
+    /// Trip id scheme, hash or readable
+    pub trip_ids: Option<String>,
+    pub route_types: Option<RouteTypeMapping>,
+    /// Colour table entries overriding the default colours
+    pub colors: Option<Vec<ColorEntry>>,
+}
+
+impl Profile {
+    /// Read and validate a profile file
+    pub fn load(path: &Path) -> Result<Profile, String> {
+        let profile: Profile = try!(read_json_file(path, "profile"));
+        try!(profile.validate().map_err(|err| format!("Invalid profile {}: {}", path.display(), err)));
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(ref credentials) = self.credentials {
+            if credentials.app_id.is_empty() || credentials.app_key.is_empty() {
+                return Err("credentials need both an app_id and an app_key".to_owned());
+            }
+        }
+
+        if let Some(ref dirs) = self.dirs {
+            for (name, dir) in vec![("cache", &dirs.cache), ("gtfs", &dirs.gtfs), ("geojson", &dirs.geojson), ("reports", &dirs.reports)] {
+                if dir.as_ref().map_or(false, |x| x.is_empty()) {
+                    return Err(format!("dirs.{} is empty", name));
+                }
+            }
+        }
+
+        if let Some(ref modes) = self.modes {
+            for mode in modes {
+                try!(validate_mode(mode).map_err(|err| format!("modes: {}", err)));
+            }
+        }
+
+        if let Some(ref calendar) = self.calendar {
+            try!(validate_calendar(calendar).map_err(|err| format!("calendar: {}", err)));
+        }
+
+        if let Some(ref scheme) = self.trip_ids {
+            try!(scheme.parse::<TripIdScheme>().map_err(|err| format!("trip_ids: {}", err)));
+        }
+
+        if let Some(overrides) = self.route_types.as_ref().and_then(|x| x.overrides.as_ref()) {
+            for (mode, &route_type) in overrides {
+                try!(validate_mode(mode).map_err(|err| format!("route_types.overrides: {}", err)));
+                try!(validate_route_type(route_type).map_err(|err| format!("route_types.overrides.{}: {}", mode, err)));
+            }
+        }
+
+        if let Some(ref colors) = self.colors {
+            for entry in colors {
+                try!(entry.validate().map_err(|err| format!("colors: {}", err)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check a mode is a known TfL mode name
+pub fn validate_mode(mode: &str) -> Result<(), String> {
+    if MODE_NAMES.contains(&mode) {
+        Ok(())
+    } else {
+        Err(format!("unknown mode \"{}\", expected one of {}", mode, MODE_NAMES.join(", ")))
+    }
+}
+
+/// Number of days of a month, months counting from 1
+fn month_days(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Check a date is an existing day written as YYYYMMDD
+pub fn validate_date(date: &str) -> Result<(), String> {
+    let valid = date.len() == 8 && date.chars().all(|c| c.is_digit(10)) && {
+        let year: u32 = date[0..4].parse().unwrap();
+        let month: u32 = date[4..6].parse().unwrap();
+        let day: u32 = date[6..8].parse().unwrap();
+        month >= 1 && month <= 12 && day >= 1 && day <= month_days(year, month)
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid date \"{}\", expected YYYYMMDD", date))
+    }
+}
+
+/// Check both dates of a calendar window and that it does not end before it
+/// starts
+pub fn validate_calendar(calendar: &CalendarWindow) -> Result<(), String> {
+    try!(validate_date(&calendar.start_date));
+    try!(validate_date(&calendar.end_date));
+
+    if calendar.start_date > calendar.end_date {
+        return Err(format!("start date {} is after end date {}", calendar.start_date, calendar.end_date));
+    }
+
+    Ok(())
+}
+
+/// Check a route type is a basic GTFS route type or an extended one
+pub fn validate_route_type(route_type: u16) -> Result<(), String> {
+    match route_type {
+        0 ... 7 | 11 | 12 | 100 ... 1799 => Ok(()),
+        _ => Err(format!("invalid route type {}", route_type)),
+    }
+}
+
+#[test]
+fn validate_test() {
+    assert_eq!(validate_date("20161031"), Ok(()));
+    assert!(validate_date("2016-10-31").is_err());
+    assert!(validate_date("20161331").is_err());
+    assert!(validate_date("20160231").is_err());
+    assert!(validate_date("20160431").is_err());
+    assert_eq!(validate_date("20160229"), Ok(()));
+    assert!(validate_date("20170229").is_err());
+    assert!(validate_date("19000229").is_err());
+    assert_eq!(validate_date("20000229"), Ok(()));
+
+    // misspelt settings are rejected rather than ignored
+    let parse = |body: &str| ::serde_json::from_str::<Profile>(body).is_ok();
+    assert!(parse(r#"{"dirs": {"gtfs": "out"}}"#));
+    assert!(!parse(r#"{"trip_id": "readable"}"#));
+    assert!(!parse(r#"{"credentials": {"app_id": "id", "app_key": "key", "secret": "x"}}"#));
+    assert!(!parse(r#"{"dirs": {"gtfs_dir": "out"}}"#));
+    assert!(!parse(r#"{"route_types": {"extend": true}}"#));
+
+    let profile = |body: &str| ::serde_json::from_str::<Profile>(body).unwrap().validate();
+    assert_eq!(profile(r#"{"modes": ["tube", "dlr"], "trip_ids": "readable", "calendar": {"start_date": "20160101", "end_date": "20161231"}}"#), Ok(()));
+    assert!(profile(r#"{"modes": ["hovercraft"]}"#).unwrap_err().starts_with("modes: unknown mode \"hovercraft\""));
+    assert!(profile(r#"{"calendar": {"start_date": "20161231", "end_date": "20160101"}}"#).is_err());
+    assert!(profile(r#"{"route_types": {"overrides": {"bus": 9999}}}"#).is_err());
+    assert!(profile(r#"{"route_types": {"overrides": {"hovercraft": 4}}}"#).is_err());
+    assert!(profile(r#"{"colors": [{"mode": "bus", "color": "red"}]}"#).is_err());
+}
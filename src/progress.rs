@@ -0,0 +1,83 @@
+use atty;
+use std::io::{self, Write};
+use std::iter;
+use std::time::Instant;
+
+/// Width of the bar in characters
+const WIDTH: usize = 30;
+
+/// Progress bar drawn on stderr with an estimate of the remaining time. It
+/// stays hidden when stderr is not a terminal, e.g. redirected to a log file.
+pub struct Progress {
+    label: &'static str,
+    total: usize,
+    done: usize,
+    start: Instant,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(label: &'static str, total: usize, enabled: bool) -> Progress {
+        Progress {
+            label: label,
+            total: total,
+            done: 0,
+            start: Instant::now(),
+            enabled: enabled && atty::is(atty::Stream::Stderr),
+        }
+    }
+
+    pub fn inc(&mut self, count: usize) {
+        self.done += count;
+        self.draw();
+    }
+
+    fn draw(&self) {
+        if !self.enabled || self.total == 0 {
+            return;
+        }
+
+        let filled = WIDTH * self.done.min(self.total) / self.total;
+        let bar: String = iter::repeat('#').take(filled).chain(iter::repeat(' ').take(WIDTH - filled)).collect();
+        let eta = remaining_seconds(self.start.elapsed().as_secs(), self.done, self.total)
+                      .map_or("?".to_owned(), duration_fmt);
+        let _ = write!(io::stderr(), "\r{} [{}] {}/{} ETA {}  ", self.label, bar, self.done, self.total, eta);
+    }
+
+    pub fn finish(&self) {
+        if self.enabled && self.total > 0 {
+            let _ = writeln!(io::stderr(), "");
+        }
+    }
+}
+
+/// Seconds left assuming the remaining items take as long as the done ones
+fn remaining_seconds(elapsed: u64, done: usize, total: usize) -> Option<u64> {
+    if done == 0 {
+        None
+    } else {
+        Some(elapsed * (total.saturating_sub(done)) as u64 / done as u64)
+    }
+}
+
+/// Format a number of seconds as 1h02m03s, 2m03s or 3s
+fn duration_fmt(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[test]
+fn remaining_seconds_test() {
+    assert_eq!(remaining_seconds(10, 0, 100), None);
+    assert_eq!(remaining_seconds(10, 25, 100), Some(30));
+    assert_eq!(remaining_seconds(10, 100, 100), Some(0));
+    assert_eq!(duration_fmt(3723), "1h02m03s");
+    assert_eq!(duration_fmt(123), "2m03s");
+    assert_eq!(duration_fmt(3), "3s");
+}
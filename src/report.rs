@@ -0,0 +1,159 @@
+use serde_json;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use geometry::LineStringDiagnostics;
+
+/// Data quality counts of a converted line
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LineReport {
+    pub line_id: String,
+    pub line_name: String,
+    pub mode: String,
+    pub route_sections: usize,
+    pub trips: usize,
+    /// Trips whose id was already in the feed and got a numbered suffix
+    pub trip_id_collisions: usize,
+    pub stop_times: usize,
+    /// Route sections without a usable timetable
+    pub missing_timetables: usize,
+    /// Route sections for which no shape could be built
+    pub failed_shapes: usize,
+    /// Whether the line is missing from the colour table
+    pub unknown_color: bool,
+    /// Schedule names which are not a calendar service
+    pub unknown_schedule_names: Vec<String>,
+    /// Line strings which could not be decoded
+    pub decode_errors: usize,
+    pub line_strings: LineStringDiagnostics,
+}
+
+/// Sum of the counts of every line
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Totals {
+    pub lines: usize,
+    pub route_sections: usize,
+    pub trips: usize,
+    pub trip_id_collisions: usize,
+    pub stop_times: usize,
+    pub missing_timetables: usize,
+    pub failed_shapes: usize,
+    pub unknown_colors: usize,
+    pub unknown_schedule_names: usize,
+    pub decode_errors: usize,
+}
+
+#[derive(Serialize)]
+struct QualityReport<'a> {
+    generated_at: u64,
+    totals: Totals,
+    lines: &'a [LineReport],
+}
+
+impl LineReport {
+    fn has_problems(&self) -> bool {
+        self.trip_id_collisions > 0 || self.missing_timetables > 0 || self.failed_shapes > 0 || self.unknown_color || !self.unknown_schedule_names.is_empty() || self.decode_errors > 0
+    }
+}
+
+fn totals(lines: &[LineReport]) -> Totals {
+    let mut totals = Totals::default();
+    let mut schedule_names: Vec<&String> = lines.iter().flat_map(|x| x.unknown_schedule_names.iter()).collect();
+    schedule_names.sort();
+    schedule_names.dedup();
+
+    for line in lines {
+        totals.lines += 1;
+        totals.route_sections += line.route_sections;
+        totals.trips += line.trips;
+        totals.trip_id_collisions += line.trip_id_collisions;
+        totals.stop_times += line.stop_times;
+        totals.missing_timetables += line.missing_timetables;
+        totals.failed_shapes += line.failed_shapes;
+        totals.decode_errors += line.decode_errors;
+        if line.unknown_color {
+            totals.unknown_colors += 1;
+        }
+    }
+    totals.unknown_schedule_names = schedule_names.len();
+
+    totals
+}
+
+/// Write the report of a run as JSON and HTML into the given directory, named
+/// after the time of the run so successive runs can be compared. Returns the
+/// path of the JSON report.
+pub fn write_report(reports_path: &str, lines: &[LineReport]) -> String {
+    let _ = fs::create_dir_all(reports_path);
+    let generated_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+    let report = QualityReport {
+        generated_at: generated_at,
+        totals: totals(lines),
+        lines: lines,
+    };
+
+    let json_path = format!("{}/quality-{}.json", reports_path, generated_at);
+    let mut f = File::create(Path::new(&json_path)).unwrap();
+    serde_json::to_writer_pretty(&mut f, &report).unwrap();
+
+    let html_path = format!("{}/quality-{}.html", reports_path, generated_at);
+    let mut f = File::create(Path::new(&html_path)).unwrap();
+    f.write_all(report_html(&report).as_bytes()).unwrap();
+
+    json_path
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+fn report_html(report: &QualityReport) -> String {
+    let mut html = String::new();
+    let totals = &report.totals;
+
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>GTFS quality report</title>\n");
+    html.push_str("<style>body { font-family: sans-serif; } table { border-collapse: collapse; } td, th { border: 1px solid #ccc; padding: 2px 6px; } td.n { text-align: right; } tr.problem { background: #fdd; }</style>\n");
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>GTFS quality report</h1>\n<p>Generated at {} (seconds since epoch)</p>\n", report.generated_at));
+    html.push_str("<table>\n<tr><th>Line</th><th>Name</th><th>Mode</th><th>Route sections</th><th>Trips</th><th>Trip id collisions</th><th>Stop times</th><th>Missing timetables</th><th>Failed shapes</th><th>Unknown colour</th><th>Unknown schedule names</th><th>Decode errors</th></tr>\n");
+    html.push_str(&format!("<tr><th colspan=\"3\">{} lines</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th></tr>\n",
+                           totals.lines, totals.route_sections, totals.trips, totals.trip_id_collisions, totals.stop_times, totals.missing_timetables,
+                           totals.failed_shapes, totals.unknown_colors, totals.unknown_schedule_names, totals.decode_errors));
+
+    for line in report.lines {
+        let class = if line.has_problems() { " class=\"problem\"" } else { "" };
+        let schedule_names: Vec<String> = line.unknown_schedule_names.iter().map(|x| escape_html(x)).collect();
+        html.push_str(&format!("<tr{}><td>{}</td><td>{}</td><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td><td>{}</td><td class=\"n\">{}</td></tr>\n",
+                               class, escape_html(&line.line_id), escape_html(&line.line_name), escape_html(&line.mode),
+                               line.route_sections, line.trips, line.trip_id_collisions, line.stop_times, line.missing_timetables, line.failed_shapes,
+                               if line.unknown_color { "yes" } else { "" }, schedule_names.join(", "), line.decode_errors));
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+#[test]
+fn totals_test() {
+    let line = |id: &str, trips, unknown_color, schedule_names: &[&str]| LineReport {
+        line_id: id.to_owned(),
+        trips: trips,
+        trip_id_collisions: 1,
+        unknown_color: unknown_color,
+        unknown_schedule_names: schedule_names.iter().map(|x| x.to_string()).collect(),
+        ..LineReport::default()
+    };
+    let lines = vec![line("1", 10, true, &["Bank Holiday"]), line("2", 5, false, &["Bank Holiday", "Christmas Day"])];
+    let totals = totals(&lines);
+
+    assert_eq!(totals.lines, 2);
+    assert_eq!(totals.trips, 15);
+    assert_eq!(totals.trip_id_collisions, 2);
+    assert_eq!(totals.unknown_colors, 1);
+    assert_eq!(totals.unknown_schedule_names, 2);
+}
@@ -0,0 +1,183 @@
+use serde_json;
+use std::collections::HashMap;
+use std::path::Path;
+
+use agency::Agency;
+use json_file::read_json_file;
+use tfl::line::{Direction, Line, Mode};
+
+/// Default route name templates, embedded at compile time
+const DEFAULT_TEMPLATES: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/route_names.json"));
+
+/// Templates used for modes without templates of their own
+const DEFAULT_MODE: &'static str = "default";
+
+/// Suffixes dropped from stop names in route long names
+const STOP_NAME_SUFFIXES: &'static [&'static str] = &[
+    " Underground Station",
+    " DLR Station",
+    " Rail Station",
+    " Tram Stop",
+    " Station",
+];
+
+#[derive(Clone, Debug, Deserialize)]
+struct TemplateEntry {
+    mode: String,
+    long_name: String,
+    desc: String,
+}
+
+/// route_long_name and route_desc templates keyed by mode. Templates may
+/// refer to {origin}, {destination}, {line}, {mode} and {agency}.
+#[derive(Clone, Debug)]
+pub struct RouteNameTemplates {
+    templates: HashMap<String, TemplateEntry>,
+}
+
+impl Default for RouteNameTemplates {
+    fn default() -> RouteNameTemplates {
+        let mut templates = RouteNameTemplates { templates: HashMap::new() };
+        templates.insert(serde_json::from_str(DEFAULT_TEMPLATES).unwrap());
+        templates
+    }
+}
+
+impl RouteNameTemplates {
+    /// Load a user template file on top of the current templates, its
+    /// entries take precedence over existing ones.
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let entries: Vec<TemplateEntry> = try!(read_json_file(path, "route name file"));
+        self.insert(entries);
+        Ok(())
+    }
+
+    fn insert(&mut self, entries: Vec<TemplateEntry>) {
+        for entry in entries {
+            self.templates.insert(entry.mode.clone(), entry);
+        }
+    }
+
+    fn templates(&self, mode: &Mode) -> Option<&TemplateEntry> {
+        self.templates.get(mode.as_str()).or_else(|| self.templates.get(DEFAULT_MODE))
+    }
+
+    /// Long name of a line, empty when its termini are unknown
+    pub fn long_name(&self, line: &Line, agency: &Agency) -> String {
+        match (self.templates(&line.mode), line_termini(line)) {
+            (Some(entry), Some((origin, destination))) => render(&entry.long_name, line, agency, &origin, &destination),
+            _ => String::new(),
+        }
+    }
+
+    /// Description of a line
+    pub fn desc(&self, line: &Line, agency: &Agency) -> String {
+        let (origin, destination) = line_termini(line).unwrap_or((String::new(), String::new()));
+        self.templates(&line.mode).map_or(String::new(), |entry| render(&entry.desc, line, agency, &origin, &destination))
+    }
+}
+
+fn render(template: &str, line: &Line, agency: &Agency, origin: &str, destination: &str) -> String {
+    template.replace("{origin}", origin)
+            .replace("{destination}", destination)
+            .replace("{line}", &line.name)
+            .replace("{mode}", mode_label(&line.mode))
+            .replace("{agency}", agency.name)
+}
+
+/// Human readable name of a mode
+fn mode_label(mode: &Mode) -> &str {
+    match *mode {
+        Mode::Bus => "Bus",
+        Mode::CableCar => "Cable Car",
+        Mode::Coach => "Coach",
+        Mode::Dlr => "DLR",
+        Mode::ElizabethLine => "Elizabeth line",
+        Mode::NationalRail => "National Rail",
+        Mode::Overground => "London Overground",
+        Mode::ReplacementBus => "Rail Replacement Bus",
+        Mode::RiverBus => "River Bus",
+        Mode::RiverTour => "River Tour",
+        Mode::TflRail => "TfL Rail",
+        Mode::Tram => "Tram",
+        Mode::Tube => "London Underground",
+        Mode::Unknown(ref name) => &name[..],
+    }
+}
+
+/// Stop name without its station type suffix
+fn short_stop_name(name: &str) -> &str {
+    for suffix in STOP_NAME_SUFFIXES {
+        if name.ends_with(suffix) {
+            return &name[.. name.len() - suffix.len()];
+        }
+    }
+    name
+}
+
+/// Origin and destination names of a line, taken from its first outbound
+/// route section or else from its first route section
+fn line_termini(line: &Line) -> Option<(String, String)> {
+    let section = line.route_sections.iter()
+                                     .find(|x| x.direction == Direction::Outbound)
+                                     .or_else(|| line.route_sections.first());
+
+    section.and_then(|section| {
+        match (section.origination_name.as_ref(), section.destination_name.as_ref()) {
+            (Some(origin), Some(destination)) => Some((short_stop_name(origin).to_owned(), short_stop_name(destination).to_owned())),
+            _ => None,
+        }
+    })
+}
+
+#[test]
+fn short_stop_name_test() {
+    assert_eq!(short_stop_name("Brixton Underground Station"), "Brixton");
+    assert_eq!(short_stop_name("Walthamstow Central Underground Station"), "Walthamstow Central");
+    assert_eq!(short_stop_name("Westminster Pier"), "Westminster Pier");
+}
+
+#[test]
+fn route_names_test() {
+    use tfl::line::RouteSection;
+
+    let section = |direction: Direction, origin: Option<&str>, destination: Option<&str>| RouteSection {
+        name: String::new(),
+        direction: direction,
+        originator: String::new(),
+        origination_name: origin.map(|x| x.to_owned()),
+        destination: String::new(),
+        destination_name: destination.map(|x| x.to_owned()),
+        timetable: None,
+    };
+    let line = |name: &str, mode: Mode, route_sections: Vec<RouteSection>| Line {
+        id: name.to_lowercase(),
+        name: name.to_owned(),
+        mode: mode,
+        route_sections: route_sections,
+        stops: None,
+        inbound_sequence: None,
+        outbound_sequence: None,
+    };
+    let agency = Agency { id: "tfl", name: "Transport For London", url: "", phone: "" };
+    let templates = RouteNameTemplates::default();
+
+    // termini come from the outbound section
+    let bus = line("73", Mode::Bus, vec![
+        section(Direction::Inbound, Some("Stoke Newington"), Some("Oxford Circus")),
+        section(Direction::Outbound, Some("Oxford Circus Underground Station"), Some("Stoke Newington")),
+    ]);
+    assert_eq!(templates.long_name(&bus, &agency), "Oxford Circus – Stoke Newington");
+    assert_eq!(templates.desc(&bus, &agency), "Bus route 73 operated by Transport For London");
+
+    // modes without templates use the default ones
+    let dlr = line("DLR", Mode::Dlr, vec![section(Direction::Outbound, Some("Bank DLR Station"), Some("Lewisham DLR Station"))]);
+    assert_eq!(templates.long_name(&dlr, &agency), "Bank – Lewisham");
+    assert_eq!(templates.desc(&dlr, &agency), "DLR operated by Transport For London");
+
+    // no long name without both termini
+    let tram = line("Tram", Mode::Tram, vec![section(Direction::Outbound, Some("Wimbledon"), None)]);
+    assert_eq!(templates.long_name(&tram, &agency), "");
+    assert_eq!(templates.long_name(&line("Tram", Mode::Tram, vec![]), &agency), "");
+    assert_eq!(templates.desc(&tram, &agency), "Tram operated by Transport For London");
+}
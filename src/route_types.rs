@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use tfl::line::Mode;
+
+/// Route type used for unknown modes in the basic scheme, a bus
+const BASIC_FALLBACK: u16 = 3;
+/// Route type used for unknown modes in the extended scheme, a bus service
+const EXTENDED_FALLBACK: u16 = 700;
+
+/// GTFS route type mapping. The extended scheme uses the Hierarchical Vehicle
+/// Type codes, overrides are keyed by TfL mode name and take precedence.
+#[derive(Clone, Debug, Default)]
+pub struct RouteTypes {
+    pub extended: bool,
+    pub overrides: HashMap<String, u16>,
+}
+
+/// Basic and extended route types of a known mode
+fn mode_route_types(mode: &Mode) -> Option<(u16, u16)> {
+    match *mode {
+        Mode::Tram => Some((0, 900)),
+        Mode::Dlr => Some((0, 400)),
+        Mode::Tube => Some((1, 402)),
+        Mode::NationalRail => Some((2, 100)),
+        Mode::Overground | Mode::TflRail | Mode::ElizabethLine => Some((2, 109)),
+        Mode::Bus => Some((3, 700)),
+        Mode::ReplacementBus => Some((3, 714)),
+        Mode::Coach => Some((3, 200)),
+        Mode::RiverBus => Some((4, 1200)),
+        Mode::RiverTour => Some((4, 1000)),
+        Mode::CableCar => Some((6, 1300)),
+        Mode::Unknown(_) => None,
+    }
+}
+
+impl RouteTypes {
+    /// Route type of a mode, unknown modes without override falling back to
+    /// a bus
+    pub fn route_type(&self, mode: &Mode) -> u16 {
+        if let Some(&route_type) = self.overrides.get(mode.as_str()) {
+            return route_type;
+        }
+
+        match (mode_route_types(mode), self.extended) {
+            (Some((basic, _)), false) => basic,
+            (Some((_, extended)), true) => extended,
+            (None, false) => BASIC_FALLBACK,
+            (None, true) => EXTENDED_FALLBACK,
+        }
+    }
+}
+
+#[test]
+fn route_type_test() {
+    let mut route_types = RouteTypes::default();
+
+    assert_eq!(route_types.route_type(&Mode::Tube), 1);
+    assert_eq!(route_types.route_type(&Mode::Overground), 2);
+    assert_eq!(route_types.route_type(&Mode::CableCar), 6);
+    assert_eq!(route_types.route_type(&Mode::from("hovercraft")), 3);
+
+    route_types.extended = true;
+    route_types.overrides.insert("hovercraft".to_owned(), 1000);
+    assert_eq!(route_types.route_type(&Mode::ReplacementBus), 714);
+    assert_eq!(route_types.route_type(&Mode::from("hovercraft")), 1000);
+}
@@ -0,0 +1,148 @@
+use std::cmp::Ordering;
+
+use geodesy::{path_distances, project_onto_polyline, segment_distance};
+use geometry::{Point, SpatialIndex};
+
+/// Shape post-processing settings
+#[derive(Clone, Debug, Default)]
+pub struct ShapeOptions {
+    /// Douglas-Peucker tolerance in metres, shapes are left untouched when None
+    pub simplify_tolerance: Option<f64>,
+    /// Insert the stop coordinates into shapes so vehicles stop at platforms
+    pub splice_stops: bool,
+}
+
+/// Apply simplification and stop splicing to a route section shape, stops
+/// being given in the order they are served.
+pub fn process_shape(path: Vec<Point>, stops: &[Point], options: &ShapeOptions) -> Vec<Point> {
+    let path = if options.splice_stops {
+        splice_stops(&path, stops)
+    } else {
+        path
+    };
+
+    match options.simplify_tolerance {
+        Some(tolerance) => {
+            let keep = nearest_indices(&path, stops);
+            simplify(&path, tolerance, &keep)
+        },
+        None => path,
+    }
+}
+
+/// Index of the path point nearest to each stop
+pub fn nearest_indices(path: &[Point], stops: &[Point]) -> Vec<usize> {
+    let mut index = SpatialIndex::new();
+    for (i, pt) in path.iter().enumerate() {
+        index.insert(*pt, i);
+    }
+
+    stops.iter().filter_map(|stop| index.nearest(stop).map(|(_, &i, _)| i)).collect()
+}
+
+/// Douglas-Peucker simplification dropping points closer than tolerance
+/// metres to the simplified line. The endpoints and the points at the `keep`
+/// indices are always preserved.
+pub fn simplify(path: &[Point], tolerance: f64, keep: &[usize]) -> Vec<Point> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let mut kept = vec![false; path.len()];
+    kept[0] = true;
+    kept[path.len() - 1] = true;
+    for &i in keep {
+        if i < path.len() {
+            kept[i] = true;
+        }
+    }
+
+    // simplify independently between each pair of consecutive kept points
+    let anchors: Vec<usize> = (0..path.len()).filter(|&i| kept[i]).collect();
+    let mut ranges: Vec<(usize, usize)> = anchors.windows(2).map(|pair| (pair[0], pair[1])).collect();
+
+    while let Some((first, last)) = ranges.pop() {
+        if last <= first + 1 {
+            continue;
+        }
+
+        let mut max_dist = 0.0;
+        let mut max_index = first;
+        for i in first + 1 .. last {
+            let dist = segment_distance(&path[i], &path[first], &path[last]);
+            if dist > max_dist {
+                max_dist = dist;
+                max_index = i;
+            }
+        }
+
+        if max_dist > tolerance {
+            kept[max_index] = true;
+            ranges.push((first, max_index));
+            ranges.push((max_index, last));
+        }
+    }
+
+    path.iter().zip(kept.iter()).filter(|&(_, &k)| k).map(|(pt, _)| *pt).collect()
+}
+
+/// Insert the stop coordinates into the path where each stop projects onto
+/// it, stops being matched in order along the path. Stops projecting onto
+/// the same segment are inserted in their order along it.
+pub fn splice_stops(path: &[Point], stops: &[Point]) -> Vec<Point> {
+    let distances = path_distances(path);
+    let mut inserts: Vec<(usize, f64, Point)> = Vec::new();
+    let mut segment = 0;
+
+    for stop in stops {
+        if let Some(projection) = project_onto_polyline(path, &distances, stop, segment) {
+            segment = projection.segment;
+            inserts.push((projection.segment, projection.fraction, *stop));
+        }
+    }
+    inserts.sort_by(|a, b| (a.0, a.1).partial_cmp(&(b.0, b.1)).unwrap_or(Ordering::Equal));
+
+    let mut spliced = Vec::with_capacity(path.len() + inserts.len());
+    let mut inserts = inserts.into_iter().peekable();
+
+    for (i, pt) in path.iter().enumerate() {
+        spliced.push(*pt);
+        while inserts.peek().map_or(false, |&(segment, _, _)| segment == i) {
+            let (_, _, stop) = inserts.next().unwrap();
+            spliced.push(stop);
+        }
+    }
+
+    spliced
+}
+
+#[test]
+fn simplify_test() {
+    let path = vec![
+        Point::new(51.5000, -0.1200),
+        Point::new(51.50001, -0.1150),
+        Point::new(51.5000, -0.1100),
+        Point::new(51.5050, -0.1050),
+        Point::new(51.5100, -0.1000),
+    ];
+
+    assert_eq!(simplify(&path, 10.0, &[]), vec![path[0], path[2], path[4]]);
+    assert_eq!(simplify(&path, 10.0, &[1]), vec![path[0], path[1], path[2], path[4]]);
+}
+
+#[test]
+fn splice_stops_test() {
+    let path = vec![Point::new(51.5000, -0.1200), Point::new(51.5000, -0.1100), Point::new(51.5100, -0.1100)];
+    let stops = vec![Point::new(51.5001, -0.1150), Point::new(51.5050, -0.1099)];
+
+    assert_eq!(splice_stops(&path, &stops), vec![path[0], stops[0], path[1], stops[1], path[2]]);
+}
+
+#[test]
+fn splice_stops_same_segment_test() {
+    let path = vec![Point::new(51.5000, -0.1200), Point::new(51.5000, -0.1100)];
+    // the second stop projects before the first one on the only segment
+    let stops = vec![Point::new(51.5001, -0.1130), Point::new(51.5001, -0.1170)];
+
+    assert_eq!(splice_stops(&path, &stops), vec![path[0], stops[1], stops[0], path[1]]);
+}
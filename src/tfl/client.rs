@@ -1,4 +1,3 @@
-use ansi_term::Colour::Red;
 use hyper::header::{Accept, qitem};
 use hyper::mime::{Mime, TopLevel, SubLevel};
 use hyper;
@@ -15,6 +14,24 @@ pub enum DataSource {
     Cache
 }
 
+/// TfL API credentials and cache location
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    pub app_id: String,
+    pub app_key: String,
+    pub cache_dir: String,
+}
+
+impl Default for ClientOptions {
+    fn default() -> ClientOptions {
+        ClientOptions {
+            app_id: String::new(),
+            app_key: String::new(),
+            cache_dir: String::from("./cache"),
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct Client {
     client: Arc<hyper::Client>,
@@ -24,15 +41,15 @@ pub struct Client {
 }
 
 impl Client {
-    pub fn new() -> Client {
-        let cache_path: &Path = Path::new("./cache");
-        let _ = fs::create_dir(cache_path);
+    pub fn new(options: &ClientOptions) -> Client {
+        let cache_path: &Path = Path::new(&options.cache_dir);
+        let _ = fs::create_dir_all(cache_path);
 
         Client {
             client : Arc::new(hyper::Client::new()),
-            app_id : String::new(),
-            app_key : String::new(),
-            cache_dir : String::from("./cache"),
+            app_id : options.app_id.clone(),
+            app_key : options.app_key.clone(),
+            cache_dir : options.cache_dir.clone(),
         }
     }
 
@@ -98,7 +115,7 @@ impl Client {
         match serde_json::from_str::<TimeTableResponse>(&body) {
             Ok(ttresp) =>  Some(ttresp.clone()),
             Err(err) => {
-                println!("{}: {}", Red.bold().paint("Error decoding timetable"), err);
+                error!("Error decoding timetable of {}: {}", req_uri, err);
                 None
             },
         }
@@ -110,7 +127,7 @@ impl Client {
         match serde_json::from_str::<Vec<Stop>>(&body) {
             Ok(stops) => stops,
             Err(err) => {
-                println!("{}: {}", Red.bold().paint("Error decoding stops"), err);
+                error!("Error decoding stops of {}: {}", req_uri, err);
                 Vec::<Stop>::new()
             }
         }
@@ -122,7 +139,7 @@ impl Client {
         match serde_json::from_str::<Sequence>(&body) {
             Ok(seq) => Some(seq),
             Err(err) => {
-                println!("{}: {}", Red.bold().paint("Error decoding sequence"), err);
+                error!("Error decoding sequence of {}: {}", req_uri, err);
                 None
             }
         }
@@ -1,135 +1,154 @@
-use ansi_term::Colour::{Green, Blue, Red};
+use ansi_term::Colour::{Green, Blue};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::collections::HashSet;
 
-#[derive(Clone, Debug, Deserialize)]
-pub struct Line {
-    pub id: String,
-    pub name: String,
-    #[serde(rename="modeName")]
-    pub mode_name: String,
-    #[serde(rename="routeSections")]
-    pub route_sections: Vec<RouteSection>,
-    pub stops: Option<Vec<Stop>>,
-    pub inbound_sequence: Option<Sequence>,
-    pub outbound_sequence: Option<Sequence>,
+/// Names of the known transport modes
+pub const MODE_NAMES: &'static [&'static str] = &[
+    "bus", "cable-car", "coach", "dlr", "elizabeth-line", "national-rail", "overground",
+    "replacement-bus", "river-bus", "river-tour", "tflrail", "tram", "tube",
+];
+
+/// Transport mode of a line, as named by TfL
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Bus,
+    CableCar,
+    Coach,
+    Dlr,
+    ElizabethLine,
+    NationalRail,
+    Overground,
+    ReplacementBus,
+    RiverBus,
+    RiverTour,
+    TflRail,
+    Tram,
+    Tube,
+    Unknown(String),
 }
 
-/// Default color string, use null so the importer can choose
-const DEFAULT_COLOR : &'static str = "";
-
-impl Line {
-    /// Tube Color
-    fn tube_color(&self) -> &str {
-        match &self.name[..] {
-            "Bakerloo" => "894E24",
-            "Central" => "DC241F",
-            "Circle" => "FFCE00",
-            "District" => "007229",
-            "Hammersmith & City" => "D799AF",
-            "Jubilee" => "6A7278",
-            "Metropolitan" => "751056",
-            "Northern" => "000",
-            "Piccadilly" => "0019A8",
-            "Victoria" => "00A0E2",
-            "Waterloo & City" => "76D0BD",
-            _ => {
-                println!("Missing tube color for {}", self.name);
-                DEFAULT_COLOR
-            },
+impl Mode {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Mode::Bus => "bus",
+            Mode::CableCar => "cable-car",
+            Mode::Coach => "coach",
+            Mode::Dlr => "dlr",
+            Mode::ElizabethLine => "elizabeth-line",
+            Mode::NationalRail => "national-rail",
+            Mode::Overground => "overground",
+            Mode::ReplacementBus => "replacement-bus",
+            Mode::RiverBus => "river-bus",
+            Mode::RiverTour => "river-tour",
+            Mode::TflRail => "tflrail",
+            Mode::Tram => "tram",
+            Mode::Tube => "tube",
+            Mode::Unknown(ref name) => &name[..],
         }
     }
+}
 
-    /// Tram Color
-    fn tram_color(&self) -> &str {
-        match &self.name[..] {
-            "Tram 1" | "Tram 2" => "C6D834",
-            "Tram 3" => "79C23F",
-            "Tram 4" => "336B14",
-            _ => {
-                println!("Missing tram color for {}", self.name);
-                DEFAULT_COLOR
-            },
+impl<'a> From<&'a str> for Mode {
+    fn from(name: &'a str) -> Mode {
+        match name {
+            "bus" => Mode::Bus,
+            "cable-car" => Mode::CableCar,
+            "coach" => Mode::Coach,
+            "dlr" => Mode::Dlr,
+            "elizabeth-line" => Mode::ElizabethLine,
+            "national-rail" => Mode::NationalRail,
+            "overground" => Mode::Overground,
+            "replacement-bus" => Mode::ReplacementBus,
+            "river-bus" => Mode::RiverBus,
+            "river-tour" => Mode::RiverTour,
+            "tflrail" => Mode::TflRail,
+            "tram" => Mode::Tram,
+            "tube" => Mode::Tube,
+            _ => Mode::Unknown(name.to_owned()),
         }
     }
+}
 
-    /// National Rail Color
-    fn national_rail_color(&self) -> &str {
-        match &self.name[..] {
-            "South West Trains" => "F11815",
-            "Southeastern" => "0071BF",
-            "Southern" => "00A74B",
-            "Great Northern" => "00A6E2",
-            "Arriva Trains Wales" => "00B9B4",
-            "c2c" => "F0188C",
-            "Chiltern Railways" => "B389C1",
-            "Cross Country" => "A03467",
-            "East Midlands Trains" => "E16C16",
-            "First Great Western" => "2D2B94",
-            "First Hull Trains" => "1B903F",
-            "First TransPennine Express" => "F265A0",
-            "Gatwick Express" => "231F20",
-            "Grand Central" => "3F3F40",
-            "Greater Anglia" => "8B8FA5",
-            "Heathrow Connect" => "F6858D",
-            "Heathrow Express" => "55C4BF",
-            "Island Line" => "F8B174",
-            "London Midland" => "8BC831",
-            "Merseyrail" => "FEC95F",
-            "Northern Rail" => "0569A8",
-            "ScotRail" => "96A3A9",
-            "Thameslink" => "DA4290",
-            "Virgin Trains" => "A8652C",
-            "Virgin Trains East Coast" => "9C0101",
-            _ => {
-                println!("Missing national rail color for {}", self.name);
-                DEFAULT_COLOR
-            },
-        }
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
     }
+}
 
-    /// River Bus Color
-    fn river_bus_color(&self) -> &str {
-        match &self.name[..] {
-            "RB1" => "2D3039",
-            "RB2" => "0072BC",
-            "RB4" => "61C29D",
-            "RB5" => "BA6830",
-            "RB6" => "DF64B0",
-            "Woolwich Ferry" => "F7931D",
-            _ => {
-                println!("Missing rail color for {}", self.name);
-                DEFAULT_COLOR
-            },
-        }
+impl Serialize for Mode {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.serialize_str(self.as_str())
     }
+}
 
-    fn cable_car_color(&self) -> &str {
-        match &self.name[..] {
-            "Emirates Air Line" => "E51937",
-            _ => {
-                println!("Missing rail color for {}", self.name);
-                DEFAULT_COLOR
-            },
+impl Deserialize for Mode {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Mode, D::Error> {
+        let name = try!(String::deserialize(deserializer));
+        Ok(Mode::from(&name[..]))
+    }
+}
+
+/// Direction of a route section or route sequence
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+    Unknown(String),
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+            Direction::Unknown(ref name) => &name[..],
         }
     }
+}
 
-    /// The Line's Color based on the TFL colors on tfl.gov.uk
-    pub fn color(&self) -> &str {
-        match &self.mode_name[..] {
-            "dlr" => "00AFAD",
-            "overground" => "E86A10",
-            "tflrail" => "0019A8",
-            "tube" => self.tube_color(),
-            "tram" => self.tram_color(),
-            "national-rail" => self.national_rail_color(),
-            "river-bus" | "river-ferry" => self.river_bus_color(),
-            "cable-car" => self.cable_car_color(),
-            _ => DEFAULT_COLOR,
+impl<'a> From<&'a str> for Direction {
+    fn from(name: &'a str) -> Direction {
+        match name {
+            "inbound" => Direction::Inbound,
+            "outbound" => Direction::Outbound,
+            _ => Direction::Unknown(name.to_owned()),
         }
     }
 }
 
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Direction {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl Deserialize for Direction {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Direction, D::Error> {
+        let name = try!(String::deserialize(deserializer));
+        Ok(Direction::from(&name[..]))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Line {
+    pub id: String,
+    pub name: String,
+    #[serde(rename="modeName")]
+    pub mode: Mode,
+    #[serde(rename="routeSections")]
+    pub route_sections: Vec<RouteSection>,
+    pub stops: Option<Vec<Stop>>,
+    pub inbound_sequence: Option<Sequence>,
+    pub outbound_sequence: Option<Sequence>,
+}
+
 impl fmt::Display for Line {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let id: &str = &self.id;
@@ -145,15 +164,21 @@ pub struct Stop {
     pub common_name: String,
     pub lat: f64,
     pub lon: f64,
+    #[serde(default)]
+    pub modes: Vec<String>,
     pub children: Vec<Stop>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct RouteSection {
     pub name: String,
-    pub direction: String,
+    pub direction: Direction,
     pub originator: String,
+    #[serde(rename="originationName")]
+    pub origination_name: Option<String>,
     pub destination: String,
+    #[serde(rename="destinationName")]
+    pub destination_name: Option<String>,
     pub timetable: Option<TimeTableResponse>,
 }
 
@@ -217,22 +242,85 @@ pub struct TimeTableResponse {
     line_id: String,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct MatchedStop {
+    pub id: String,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(rename="stationId")]
+    pub station_id: Option<String>,
+    #[serde(rename="topMostParentId")]
+    pub top_most_parent_id: Option<String>,
+    #[serde(rename="stopLetter")]
+    pub stop_letter: Option<String>,
+    pub towards: Option<String>,
+    #[serde(default)]
+    pub modes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StopPointSequence {
+    pub direction: Direction,
+    #[serde(rename="branchId")]
+    pub branch_id: i64,
+    #[serde(rename="nextBranchIds")]
+    #[serde(default)]
+    pub next_branch_ids: Vec<i64>,
+    #[serde(rename="prevBranchIds")]
+    #[serde(default)]
+    pub prev_branch_ids: Vec<i64>,
+    #[serde(rename="stopPoint")]
+    pub stop_point: Vec<MatchedStop>,
+    #[serde(rename="serviceType")]
+    pub service_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OrderedLineRoute {
+    pub name: String,
+    #[serde(rename="naptanIds")]
+    pub naptan_ids: Vec<String>,
+    #[serde(rename="serviceType")]
+    pub service_type: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Sequence {
     #[serde(rename="lineStrings")]
     pub line_strings: Vec<String>,
+    #[serde(default)]
+    pub stations: Vec<MatchedStop>,
+    #[serde(rename="stopPointSequences")]
+    #[serde(default)]
+    pub stop_point_sequences: Vec<StopPointSequence>,
+    #[serde(rename="orderedLineRoutes")]
+    #[serde(default)]
+    pub ordered_line_routes: Vec<OrderedLineRoute>,
+}
+
+impl Sequence {
+    pub fn branch(&self, branch_id: i64) -> Option<&StopPointSequence> {
+        self.stop_point_sequences.iter().find(|x| x.branch_id == branch_id)
+    }
 }
 
 impl TimeTableResponse {
+    /// First timetable of the response, None when TfL returned an error.
+    /// Errors are reported once by the caller.
     pub fn first_timetable(&self) -> Option<&TimeTable> {
-        if let Some(ref message) = self.status_error_message {
-            println!("{} (line {}): {}", Red.bold().paint("Error"), Blue.bold().paint(self.line_id.clone()), message);
+        if self.status_error_message.is_some() {
             None
         } else {
-            Some(&self.timetable.routes[0])
+            self.timetable.routes.first()
         }
     }
 
+    /// Whether the response holds a timetable
+    pub fn has_timetable(&self) -> bool {
+        self.status_error_message.is_none() && !self.timetable.routes.is_empty()
+    }
+
     pub fn schedule_names(&self) -> HashSet<String> {
         if let Some(record) = *&self.first_timetable() {
             return record.schedules.iter()
@@ -243,3 +331,22 @@ impl TimeTableResponse {
         HashSet::new()
     }
 }
+
+#[test]
+fn mode_names_test() {
+    for name in MODE_NAMES {
+        let mode = Mode::from(*name);
+        assert!(mode != Mode::Unknown(name.to_string()), "{} is not a known mode", name);
+        assert_eq!(mode.as_str(), *name);
+    }
+}
+
+#[test]
+fn mode_test() {
+    assert_eq!(Mode::from("national-rail"), Mode::NationalRail);
+    assert_eq!(Mode::from("hovercraft"), Mode::Unknown("hovercraft".to_owned()));
+    assert_eq!(Mode::from("hovercraft").as_str(), "hovercraft");
+    assert_eq!(Mode::from(Mode::ElizabethLine.as_str()), Mode::ElizabethLine);
+    assert_eq!(Direction::from("inbound"), Direction::Inbound);
+    assert_eq!(Direction::from("both").as_str(), "both");
+}
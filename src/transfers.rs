@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, HashMap};
+
+use geometry::{Point, SpatialIndex};
+use tfl::line::{Line, Stop};
+
+/// Transfer generation settings
+#[derive(Clone, Debug)]
+pub struct TransferOptions {
+    /// Maximum walking distance in metres between stops of different stations
+    pub max_distance: f64,
+    /// Walking speed in metres per second
+    pub walking_speed: f64,
+    /// Minimum transfer time in seconds used when no mode pair matches
+    pub default_min_time: u64,
+    /// Minimum transfer time in seconds keyed by (from mode, to mode)
+    pub mode_min_times: HashMap<(String, String), u64>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> TransferOptions {
+        let mut mode_min_times = HashMap::new();
+        let defaults = [
+            ("bus", "bus", 60),
+            ("tube", "tube", 180),
+            ("tube", "dlr", 240),
+            ("tube", "overground", 300),
+            ("tube", "national-rail", 300),
+            ("tube", "tflrail", 300),
+            ("national-rail", "national-rail", 300),
+        ];
+
+        for &(from_mode, to_mode, seconds) in &defaults {
+            mode_min_times.insert((from_mode.to_owned(), to_mode.to_owned()), seconds);
+        }
+
+        TransferOptions {
+            max_distance: 250.0,
+            walking_speed: 1.2,
+            default_min_time: 120,
+            mode_min_times: mode_min_times,
+        }
+    }
+}
+
+impl TransferOptions {
+    /// Minimum transfer time between two modes, mode pairs are symmetric
+    pub fn min_time(&self, from_mode: &str, to_mode: &str) -> u64 {
+        let forward = (from_mode.to_owned(), to_mode.to_owned());
+        let backward = (to_mode.to_owned(), from_mode.to_owned());
+
+        self.mode_min_times.get(&forward)
+                           .or_else(|| self.mode_min_times.get(&backward))
+                           .cloned()
+                           .unwrap_or(self.default_min_time)
+    }
+}
+
+/// Transfer between two stops, written as transfer_type 2
+pub struct Transfer {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub min_transfer_time: u64,
+}
+
+/// Stop point taking part in transfers
+struct TransferStop {
+    id: String,
+    /// NaPTAN id of the parent station, or the stop itself at top level
+    station: String,
+    mode: String,
+    point: Point,
+}
+
+/// Mode of a stop as served by a line, preferring the line's own mode
+fn stop_mode(stop: &Stop, line_mode: &str) -> String {
+    if stop.modes.is_empty() || stop.modes.iter().any(|mode| mode == line_mode) {
+        line_mode.to_owned()
+    } else {
+        stop.modes[0].clone()
+    }
+}
+
+fn transfer_stops(lines: &[&Line]) -> Vec<TransferStop> {
+    let mut stops = BTreeMap::<String, TransferStop>::new();
+
+    for line in lines {
+        if let Some(ref line_stops) = line.stops {
+            for stop in line_stops {
+                let point = Point::new(stop.lat, stop.lon);
+
+                stops.entry(stop.naptan_id.clone()).or_insert(TransferStop {
+                    id: stop.naptan_id.clone(),
+                    station: stop.naptan_id.clone(),
+                    mode: stop_mode(stop, line.mode.as_str()),
+                    point: point,
+                });
+
+                for child in &stop.children {
+                    stops.entry(child.naptan_id.clone()).or_insert(TransferStop {
+                        id: child.naptan_id.clone(),
+                        station: stop.naptan_id.clone(),
+                        mode: stop_mode(child, line.mode.as_str()),
+                        point: point,
+                    });
+                }
+            }
+        }
+    }
+
+    stops.into_iter().map(|(_, stop)| stop).collect()
+}
+
+fn insert_transfer(transfers: &mut BTreeMap<(String, String), u64>, from: &TransferStop, to: &TransferStop, min_time: u64) {
+    let key = (from.id.clone(), to.id.clone());
+    let time = transfers.entry(key).or_insert(min_time);
+
+    if *time < min_time {
+        *time = min_time;
+    }
+}
+
+/// Derive transfers between stops sharing a parent station and between
+/// stops within walking distance of each other. Transfers are returned in
+/// both directions, ordered by stop ids.
+pub fn transfers(lines: &[&Line], options: &TransferOptions) -> Vec<Transfer> {
+    let stops = transfer_stops(lines);
+    let mut transfers = BTreeMap::<(String, String), u64>::new();
+
+    // stops sharing a parent station
+    {
+        let mut stations = BTreeMap::<&str, Vec<&TransferStop>>::new();
+        for stop in &stops {
+            stations.entry(&stop.station[..]).or_insert_with(Vec::new).push(stop);
+        }
+
+        for members in stations.values() {
+            for from in members {
+                for to in members {
+                    if from.id != to.id {
+                        insert_transfer(&mut transfers, from, to, options.min_time(&from.mode, &to.mode));
+                    }
+                }
+            }
+        }
+    }
+
+    // stops of different stations within walking distance
+    let mut index = SpatialIndex::new();
+    for (i, stop) in stops.iter().enumerate() {
+        index.insert(stop.point, i);
+    }
+
+    for (i, from) in stops.iter().enumerate() {
+        for (_, &j, distance) in index.within(&from.point, options.max_distance) {
+            let to = &stops[j];
+
+            if j <= i || from.station == to.station {
+                continue;
+            }
+
+            let walking_time = (distance / options.walking_speed).ceil() as u64;
+            let min_time = options.min_time(&from.mode, &to.mode);
+            let time = if walking_time > min_time { walking_time } else { min_time };
+
+            insert_transfer(&mut transfers, from, to, time);
+            insert_transfer(&mut transfers, to, from, time);
+        }
+    }
+
+    transfers.into_iter().map(|((from_stop_id, to_stop_id), min_transfer_time)| {
+        Transfer {
+            from_stop_id: from_stop_id,
+            to_stop_id: to_stop_id,
+            min_transfer_time: min_transfer_time,
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+fn test_stop(id: &str, lat: f64, modes: &[&str], children: Vec<Stop>) -> Stop {
+    Stop {
+        naptan_id: id.to_owned(),
+        common_name: id.to_owned(),
+        lat: lat,
+        lon: -0.1,
+        modes: modes.iter().map(|x| x.to_string()).collect(),
+        children: children,
+    }
+}
+
+#[cfg(test)]
+fn test_line(stops: Vec<Stop>) -> Line {
+    use tfl::line::Mode;
+
+    Line {
+        id: "test".to_owned(),
+        name: "test".to_owned(),
+        mode: Mode::Bus,
+        route_sections: vec![],
+        stops: Some(stops),
+        inbound_sequence: None,
+        outbound_sequence: None,
+    }
+}
+
+#[cfg(test)]
+fn transfer_pairs(transfers: &[Transfer]) -> Vec<(&str, &str)> {
+    transfers.iter().map(|x| (&x.from_stop_id[..], &x.to_stop_id[..])).collect()
+}
+
+#[test]
+fn station_transfers_test() {
+    let station = test_stop("a", 51.5, &[], vec![test_stop("a1", 51.5, &["tube"], vec![]), test_stop("a2", 51.5, &["bus"], vec![])]);
+    let line = test_line(vec![station]);
+    let transfers = transfers(&[&line], &TransferOptions::default());
+    let pairs = transfer_pairs(&transfers);
+
+    assert!(pairs.contains(&("a1", "a2")));
+    assert!(pairs.contains(&("a2", "a1")));
+    assert_eq!(transfers.iter().find(|x| x.from_stop_id == "a1" && x.to_stop_id == "a2").unwrap().min_transfer_time, 120);
+}
+
+#[test]
+fn walking_distance_test() {
+    // b is about 100 metres from a, c about 1.1 kilometres
+    let line = test_line(vec![test_stop("a", 51.5, &[], vec![]), test_stop("b", 51.5009, &[], vec![]), test_stop("c", 51.51, &[], vec![])]);
+    let transfers = transfers(&[&line], &TransferOptions::default());
+
+    assert_eq!(transfer_pairs(&transfers), vec![("a", "b"), ("b", "a")]);
+    // walking 100 metres at 1.2 m/s takes longer than the bus to bus minimum
+    let walking_time = (Point::new(51.5, -0.1).geo_distance(&Point::new(51.5009, -0.1)) / 1.2).ceil() as u64;
+    assert!(walking_time > 60);
+    assert_eq!(transfers[0].min_transfer_time, walking_time);
+}
+
+#[test]
+fn min_time_test() {
+    let options = TransferOptions::default();
+
+    assert_eq!(options.min_time("tube", "dlr"), 240);
+    assert_eq!(options.min_time("dlr", "tube"), 240);
+    assert_eq!(options.min_time("tram", "river-bus"), 120);
+}